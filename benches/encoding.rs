@@ -0,0 +1,84 @@
+use criterion::{ black_box, criterion_group, criterion_main, Criterion };
+use scratchback::encoding::{ Encoding, EncodingTable };
+
+/// A chat message as the relay sees it: a few items, mostly lowercase text.
+const ITEMS: &[&str] = &[
+    "Walter White",
+    "hey, is anyone up for another round? the new level is pretty fun",
+    "1720000000",
+    "1",
+];
+
+/// How encoding worked before `stream`: a `String` per character, joined at the end, and `atoi` per pair.
+mod baseline {
+    use super::EncodingTable;
+
+    pub fn encode(input: &str) -> Option<String> {
+        let mut seq = Vec::new();
+        for chr in input.chars() {
+            let mut bf = itoa::Buffer::new();
+            seq.push(format!("{:0>2}", bf.format(EncodingTable::encode(chr)?)));
+        }
+        Some(seq.join(""))
+    }
+
+    pub fn decode_items(numbers: &str) -> Option<Vec<String>> {
+        let mut decoded = Vec::new();
+        let mut s = String::new();
+        for pair in numbers.as_bytes().chunks(2) {
+            match EncodingTable::decode(atoi::atoi::<usize>(pair)?)? {
+                '•' => decoded.push(core::mem::take(&mut s)),
+                chr => s.push(chr),
+            }
+        }
+        if !numbers.is_empty() {
+            decoded.push(s);
+        }
+        Some(decoded)
+    }
+}
+
+fn encode(c: &mut Criterion) {
+    let text = ITEMS.join(Encoding::SPLITTER_STR);
+    let mut group = c.benchmark_group("encode");
+
+    group.bench_function("baseline", |b| b.iter(|| baseline::encode(black_box(&text))));
+    group.bench_function("encode", |b| b.iter(|| Encoding::encode(black_box(&text))));
+    let mut out = String::new();
+    group.bench_function("try_encode_into", |b| {
+        b.iter(|| {
+            out.clear();
+            Encoding::try_encode_into(black_box(&text), &mut out).unwrap();
+        })
+    });
+    let mut out = Vec::new();
+    group.bench_function("try_encode_io", |b| {
+        b.iter(|| {
+            out.clear();
+            Encoding::try_encode_io(black_box(&text), &mut out).unwrap();
+        })
+    });
+    group.finish();
+}
+
+fn decode(c: &mut Criterion) {
+    let numbers = Encoding::encode_items(ITEMS).unwrap();
+    let mut group = c.benchmark_group("decode");
+
+    group.bench_function("baseline", |b| b.iter(|| baseline::decode_items(black_box(&numbers))));
+    group.bench_function("decode_items", |b| b.iter(|| Encoding::decode_items(black_box(&numbers))));
+    let mut out = String::new();
+    group.bench_function("items", |b| {
+        b.iter(|| {
+            for item in Encoding::items(black_box(&numbers)) {
+                out.clear();
+                item.unwrap().decode_into(&mut out).unwrap();
+                black_box(&out);
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, encode, decode);
+criterion_main!(benches);
@@ -1,10 +1,10 @@
-use std::collections::HashMap;
+use std::collections::{ BTreeMap, BTreeSet };
 
 use proc_macro::TokenStream;
-use proc_macro2::{ Ident, TokenTree };
+use proc_macro2::{ Ident, TokenStream as TokenStream2, TokenTree };
 
-use venial::{ parse_item, Error, Fields, GenericArg, Item, TypeExpr };
-use quote::quote;
+use venial::{ parse_item, Attribute, Error, Fields, GenericArg, Item, NamedFields, TypeExpr };
+use quote::{ format_ident, quote, ToTokens };
 
 macro_rules! ok_or_rt {
     ($e:expr) => {
@@ -17,269 +17,714 @@ macro_rules! ok_or_rt {
     };
 }
 
-fn derive(input: TokenStream) -> Result<TokenStream, Error> {
-    let item = ok_or_rt!(parse_item(input.into()));
+/// Options from `#[scratch(...)]` on the struct or enum itself.
+#[derive(Default)]
+struct Options {
+    /// Use `ExtendedEncoding` instead of `Encoding`.
+    extended: bool,
+    /// Use `CompactEncoding` instead of `Encoding`.
+    compact: bool,
+    /// Use `TableEncoding` with this table instead of `Encoding`, from `table = path`.
+    table: Option<TokenStream2>,
+    /// Written as the first item, from `version = n`.
+    version: Option<u32>,
+}
 
-    match item {
-        Item::Struct(st) => {
-            let Fields::Named(fields) = st.fields else {
-                return Err(Error::new_at_span(st.fields.span(), "Expected named fields"));
+fn options(attributes: &[Attribute]) -> Result<Options, Error> {
+    let mut options = Options::default();
+    for attr in attributes {
+        if attr.path.last().unwrap().to_string() != "scratch" {
+            continue;
+        }
+
+        let mut tokens = attr.get_value_tokens().iter();
+        while let Some(token) = tokens.next() {
+            match token {
+                TokenTree::Ident(ident) if ident == "extended" => {
+                    options.extended = true;
+                }
+                TokenTree::Ident(ident) if ident == "compact" => {
+                    options.compact = true;
+                }
+                TokenTree::Ident(ident) if ident == "table" => {
+                    let table = match tokens.next() {
+                        Some(TokenTree::Punct(eq)) if eq.as_char() == '=' => {
+                            tokens
+                                .by_ref()
+                                .take_while(|token| !matches!(token, TokenTree::Punct(punct) if punct.as_char() == ','))
+                                .cloned()
+                                .collect::<TokenStream2>()
+                        }
+                        _ => TokenStream2::new(),
+                    };
+                    if table.is_empty() {
+                        return Err(Error::new_at_span(ident.span(), "Expected #[scratch(table = MyTable)]"));
+                    }
+                    options.table = Some(table);
+                }
+                TokenTree::Ident(ident) if ident == "version" => {
+                    let version = match (tokens.next(), tokens.next()) {
+                        (Some(TokenTree::Punct(eq)), Some(TokenTree::Literal(lit))) if eq.as_char() == '=' => {
+                            lit.to_string().parse::<u32>().ok()
+                        }
+                        _ => None,
+                    };
+                    let Some(version) = version else {
+                        return Err(Error::new_at_span(ident.span(), "Expected #[scratch(version = n)] (range: u32)"));
+                    };
+                    options.version = Some(version);
+                }
+                TokenTree::Punct(punct) if punct.as_char() == ',' => {}
+                _ => {
+                    return Err(
+                        Error::new_at_span(
+                            token.span(),
+                            "Expected #[scratch(extended)], #[scratch(compact)], #[scratch(table = MyTable)] or #[scratch(version = n)]"
+                        )
+                    );
+                }
+            }
+        }
+        if [options.extended, options.compact, options.table.is_some()].into_iter().filter(|set| *set).count() > 1 {
+            return Err(Error::new_at_span(attr.span(), "Choose one of extended, compact or table"));
+        }
+    }
+    Ok(options)
+}
+
+/// The code that depends on the encoding `#[scratch(...)]` chose, shared by structs and enums.
+struct CodecCode {
+    /// The encoding type, used as `<#encoding>::try_decode`.
+    encoding: TokenStream2,
+    /// An `ItemReader` over `text`.
+    reader: TokenStream2,
+    writer: TokenStream2,
+    /// Overrides of `sb_decode_text` and `sb_encode_text`, if the default ones do not fit.
+    nested_text: TokenStream2,
+    extended: bool,
+    compact: bool,
+    schema_table: TokenStream2,
+    schema_version: TokenStream2,
+}
+
+fn codec_code(options: &Options) -> CodecCode {
+    let encoding = if options.extended {
+        quote! { ::scratchback::encoding::ExtendedEncoding }
+    } else if options.compact {
+        quote! { ::scratchback::encoding::CompactEncoding }
+    } else if let Some(table) = &options.table {
+        quote! { ::scratchback::encoding::TableEncoding<#table> }
+    } else {
+        quote! { ::scratchback::encoding::Encoding }
+    };
+    let extended = options.extended;
+    let compact = options.compact;
+    let reader = if compact {
+        quote! { ItemReader::compact(&text) }
+    } else {
+        quote! { ItemReader::new(&text, #extended) }
+    };
+    let writer = match &options.table {
+        Some(table) => quote! { ItemWriter::with_table::<#table>() },
+        None => quote! { ItemWriter::new(#extended) },
+    };
+    // Nested values are stored as their text, which only the type's own table can turn back into digits.
+    let nested_text = if compact || options.table.is_some() {
+        quote! {
+            fn sb_decode_text(numbers: &str) -> Result<String, ::scratchback::encoding::EncodingError> {
+                <#encoding>::try_decode(numbers)
+            }
+            fn sb_encode_text(text: &str) -> Result<String, ::scratchback::encoding::EncodingError> {
+                <#encoding>::try_encode(text)
+            }
+        }
+    } else {
+        quote! {}
+    };
+    let schema_table = match &options.table {
+        Some(table) => quote! { Some(<#table as ::scratchback::encoding::Table>::CHARS) },
+        None => quote! { None },
+    };
+    let schema_version = match options.version {
+        Some(version) => quote! { Some(#version) },
+        None => quote! { None },
+    };
+    CodecCode { encoding, reader, writer, nested_text, extended, compact, schema_table, schema_version }
+}
+
+/// What a field is set to when a shorter (older) value does not have its item.
+enum Missing {
+    /// The value cannot be decoded.
+    Required,
+    /// `Default::default()`, from `#[id(n, default)]` or for `Option` fields.
+    Default,
+    /// From `#[id(n, default = expr)]`.
+    Expr(TokenStream2),
+}
+
+/// Parse the tokens after the id: nothing, `, default` or `, default = expr`.
+fn missing(tokens: &[TokenTree], ty: &TypeExpr) -> Result<Missing, Error> {
+    let is_comma = |token: &TokenTree| matches!(token, TokenTree::Punct(punct) if punct.as_char() == ',');
+    let is_default = |token: &TokenTree| matches!(token, TokenTree::Ident(ident) if ident == "default");
+    let is_eq = |token: &TokenTree| matches!(token, TokenTree::Punct(punct) if punct.as_char() == '=');
+
+    match tokens {
+        [] => {
+            let is_option = ty
+                .as_path()
+                .and_then(|path| path.segments.last().map(|segment| segment.ident == "Option"))
+                .unwrap_or(false);
+            Ok(if is_option { Missing::Default } else { Missing::Required })
+        }
+        [comma, default] if is_comma(comma) && is_default(default) => Ok(Missing::Default),
+        [comma, default, eq, expr @ ..] if is_comma(comma) && is_default(default) && is_eq(eq) && !expr.is_empty() => {
+            Ok(Missing::Expr(expr.iter().cloned().collect()))
+        }
+        [token, ..] => Err(Error::new_at_span(token.span(), "Expected #[id(n, default)] or #[id(n, default = ...)]")),
+    }
+}
+
+/// The `#[id]` fields of a struct or struct variant.
+struct IdFields {
+    map: BTreeMap<u8, (Ident, TypeExpr, Missing)>,
+    flatten: Option<Ident>,
+}
+
+fn id_fields(fields: &NamedFields) -> Result<IdFields, Error> {
+    let mut flattens_to: Option<Ident> = None;
+    let mut map: BTreeMap<u8, (Ident, TypeExpr, Missing)> = BTreeMap::new();
+
+    for field in fields.fields.items() {
+        let mut has_id = false;
+        for attr in &field.attributes {
+            let name = &attr.path.last().unwrap().to_string();
+            if name != "id" {
+                continue;
+            }
+
+            let tokens = attr.get_value_tokens();
+            let Some(value_tokens) = tokens.first() else {
+                return Err(
+                    Error::new_at_span(attr.span(), "Expected #[id(...)], got no value")
+                );
             };
 
-            let mut flattens_to: Option<Ident> = None;
-            let mut has_id = false;
-            let mut map: HashMap<u8, Ident> = HashMap::new();
+            has_id = true;
+            match value_tokens {
+                TokenTree::Literal(lit) => {
+                    let Ok(id) = lit.to_string().parse::<u8>() else {
+                        return Err(
+                            Error::new_at_span(
+                                lit.span(),
+                                "Cannot parse into u8 (range: 0-255)"
+                            )
+                        );
+                    };
 
-            for field in fields.fields.items() {
-                for attr in &field.attributes {
-                    let name = &attr.path.last().unwrap().to_string();
-                    if name != "id" {
-                        continue;
+                    if map.contains_key(&id) {
+                        return Err(
+                            Error::new_at_span(lit.span(), "This id already exists")
+                        );
                     }
 
-                    let Some(value_tokens) = attr.get_value_tokens().first() else {
+                    let missing = ok_or_rt!(missing(&tokens[1..], &field.ty));
+                    map.insert(id, (field.name.clone(), field.ty.clone(), missing));
+                }
+                TokenTree::Ident(ident) => {
+                    let identifier = &ident.to_string();
+                    if identifier != "flatten" {
                         return Err(
-                            Error::new_at_span(attr.span(), "Expected #[id(...)], got no value")
+                            Error::new_at_span(
+                                ident.span(),
+                                "Expected either a numeric literal (u8) or `flatten`:\n#[id(1)]\n#[id(flatten)]"
+                            )
+                        );
+                    }
+
+                    let path = field.ty.as_path().unwrap();
+                    let last_typ = path.segments.last().unwrap();
+                    if &last_typ.ident.to_string() != "Vec" {
+                        return Err(
+                            Error::new_at_span(
+                                field.ty.span(),
+                                "Expected Vec<String> for #[id(flatten)]"
+                            )
+                        );
+                    }
+                    let generic_args = last_typ.generic_args.as_ref().unwrap();
+                    let first_t = generic_args.args.first().unwrap();
+                    let GenericArg::TypeOrConst { expr } = &first_t.0 else {
+                        return Err(
+                            Error::new_at_span(
+                                field.ty.span(),
+                                "Expected Vec<String> for #[id(flatten)]"
+                            )
+                        );
+                    };
+                    let TokenTree::Ident(vec_t_token) = expr.tokens.first().unwrap() else {
+                        return Err(
+                            Error::new_at_span(
+                                field.ty.span(),
+                                "Expected Vec<String> for #[id(flatten)]"
+                            )
                         );
                     };
 
-                    has_id = true;
-                    match value_tokens {
-                        TokenTree::Literal(lit) => {
-                            let Ok(id) = lit.to_string().parse::<u8>() else {
-                                return Err(
-                                    Error::new_at_span(
-                                        lit.span(),
-                                        "Cannot parse into u8 (range: 0-255)"
-                                    )
-                                );
-                            };
+                    if &vec_t_token.to_string() != "String" {
+                        return Err(
+                            Error::new_at_span(field.ty.span(), "Expected Vec<String>")
+                        );
+                    }
 
-                            if map.contains_key(&id) {
-                                return Err(
-                                    Error::new_at_span(lit.span(), "This id already exists")
-                                );
-                            }
+                    if let Some(token) = tokens.get(1) {
+                        return Err(
+                            Error::new_at_span(token.span(), "A flattened field is empty when missing already")
+                        );
+                    }
 
-                            map.insert(id, field.name.clone());
-                        }
-                        TokenTree::Ident(ident) => {
-                            let identifier = &ident.to_string();
-                            if identifier != "flatten" {
-                                return Err(
-                                    Error::new_at_span(
-                                        ident.span(),
-                                        "Expected either a numeric literal (u8) or `flatten`:\n#[id(1)]\n#[id(flatten)]"
-                                    )
-                                );
-                            }
+                    if flattens_to.is_some() {
+                        return Err(
+                            Error::new_at_span(
+                                ident.span(),
+                                "Can only have one flattened item at the end"
+                            )
+                        );
+                    }
 
-                            let path = field.ty.as_path().unwrap();
-                            let last_typ = path.segments.last().unwrap();
-                            if &last_typ.ident.to_string() != "Vec" {
-                                return Err(
-                                    Error::new_at_span(
-                                        field.ty.span(),
-                                        "Expected Vec<String> for #[id(flatten)]"
-                                    )
-                                );
-                            }
-                            let generic_args = last_typ.generic_args.as_ref().unwrap();
-                            let first_t = generic_args.args.first().unwrap();
-                            let GenericArg::TypeOrConst { expr } = &first_t.0 else {
-                                return Err(
-                                    Error::new_at_span(
-                                        field.ty.span(),
-                                        "Expected Vec<String> for #[id(flatten)]"
-                                    )
-                                );
-                            };
-                            let TokenTree::Ident(vec_t_token) = expr.tokens.first().unwrap() else {
-                                return Err(
-                                    Error::new_at_span(
-                                        field.ty.span(),
-                                        "Expected Vec<String> for #[id(flatten)]"
-                                    )
-                                );
-                            };
+                    flattens_to = Some(field.name.clone());
+                }
+                _ => {
+                    return Err(
+                        Error::new_at_span(
+                            value_tokens.span(),
+                            "Expected either a numeric literal (u8) or `flatten`:\n#[id(1)]\n#[id(flatten)]"
+                        )
+                    );
+                }
+            }
+        }
 
-                            if &vec_t_token.to_string() != "String" {
-                                return Err(
-                                    Error::new_at_span(field.ty.span(), "Expected Vec<String>")
-                                );
-                            }
+        if !has_id {
+            return Err(Error::new_at_span(field.span(), "Assign an ID: #[id(...)]"));
+        }
+    }
 
-                            if flattens_to.is_some() {
-                                return Err(
-                                    Error::new_at_span(
-                                        ident.span(),
-                                        "Can only have one flattened item at the end"
-                                    )
-                                );
-                            }
+    Ok(IdFields { map, flatten: flattens_to })
+}
 
-                            flattens_to = Some(field.name.clone());
-                        }
-                        _ => {
-                            return Err(
-                                Error::new_at_span(
-                                    value_tokens.span(),
-                                    "Expected either a numeric literal (u8) or `flatten`:\n#[id(1)]\n#[id(flatten)]"
-                                )
-                            );
-                        }
-                    }
-                }
+/// Generated code reading and writing the items of some `#[id]` fields with `items`.
+struct ItemsCode {
+    /// Every field, the flattened one last.
+    names: Vec<Ident>,
+    types: Vec<TypeExpr>,
+    read: TokenStream2,
+    write: TokenStream2,
+    /// A `&[Field]` for the schema.
+    schema_fields: TokenStream2,
+    schema_flatten: TokenStream2,
+}
 
-                if !has_id {
-                    return Err(Error::new_at_span(field.span(), "Assign an ID: #[id(...)]"));
+fn items_code(fields: &IdFields) -> ItemsCode {
+    let IdFields { map, flatten } = fields;
+    // Items sit at the position of their id, gaps stay empty.
+    let items_n = map.keys().last().map_or(0, |id| *id as usize + 1);
+    let mut names = map.values().map(|(field, ..)| field.clone()).collect::<Vec<_>>();
+    let types = map.values().map(|(_, typ, _)| typ.clone()).collect::<Vec<_>>();
+    // Items after the last id go to the flattened field.
+    let (flatten_de, flatten_en, schema_flatten) = match flatten {
+        Some(rest) => {
+            names.push(rest.clone());
+            (
+                quote! {
+                    let mut #rest = Vec::new();
+                    while !items.is_empty() {
+                        #rest.push(items.item()?.to_string());
+                    }
+                },
+                quote! {
+                    for (index, item) in #rest.into_iter().enumerate() {
+                        let offset = items.offset();
+                        items.item(&item).map_err(|err| {
+                            err.in_field((#items_n + index) as u8, ::core::stringify!(#rest), offset)
+                        })?;
+                    }
+                },
+                quote! { Some(::core::stringify!(#rest)) },
+            )
+        }
+        // Items of fields added in newer versions are ignored.
+        None => (quote! {}, quote! {}, quote! { None }),
+    };
+    let mapped_de_items = (0..items_n).map(|idx| {
+        let id = idx as u8;
+        match map.get(&id) {
+            Some((field, typ, missing)) => {
+                let read = quote! {
+                    <#typ as ScratchField>::read_from(&mut items)
+                        .map_err(|err| err.in_field(#id, ::core::stringify!(#field), offset))?
+                };
+                let fallback = match missing {
+                    Missing::Required => None,
+                    Missing::Default => Some(quote! { ::core::default::Default::default() }),
+                    Missing::Expr(expr) => Some(quote! { #expr }),
+                };
+                match fallback {
+                    Some(fallback) => quote! {
+                        let offset = items.offset();
+                        let #field = if items.is_empty() { #fallback } else { #read };
+                    },
+                    None => quote! {
+                        let offset = items.offset();
+                        let #field = #read;
+                    },
                 }
             }
+            // A gap may be missing if the fields after it are.
+            None => quote! {
+                if !items.is_empty() {
+                    items.item()?;
+                }
+            },
+        }
+    });
+    let mapped_en_items = (0..items_n).map(|idx| {
+        let id = idx as u8;
+        match map.get(&id) {
+            Some((field, ..)) => quote! {
+                let offset = items.offset();
+                ScratchField::write_to(#field, &mut items)
+                    .map_err(|err| err.in_field(#id, ::core::stringify!(#field), offset))?;
+            },
+            None => quote! { items.item("")?; },
+        }
+    });
+    let schema_fields = map.iter().map(|(id, (field, typ, missing))| {
+        let default = !matches!(missing, Missing::Required);
+        quote! {
+            Field { id: #id, name: ::core::stringify!(#field), ty: ::core::stringify!(#typ), default: #default }
+        }
+    });
+
+    ItemsCode {
+        names,
+        types,
+        read: quote! {
+            #( #mapped_de_items )*
+            #flatten_de
+        },
+        write: quote! {
+            #( #mapped_en_items )*
+            #flatten_en
+        },
+        schema_fields: quote! { &[#(#schema_fields, )*] },
+        schema_flatten,
+    }
+}
+
+fn derive(input: TokenStream) -> Result<TokenStream, Error> {
+    let item = ok_or_rt!(parse_item(input.into()));
+
+    match item {
+        Item::Struct(st) => {
+            let options = ok_or_rt!(options(&st.attributes));
+            let Fields::Named(fields) = st.fields else {
+                return Err(Error::new_at_span(st.fields.span(), "Expected named fields"));
+            };
+            let fields = ok_or_rt!(id_fields(&fields));
+            let ItemsCode { names, read, write, schema_fields, schema_flatten, .. } = items_code(&fields);
 
             let name = st.name;
-            let mut field_names = Vec::new();
-            let mut max_id = 0_u8;
-            let mapped_de_items = map.iter().map(|(k, v)| {
-                max_id = max_id.max(*k);
-                field_names.push(v);
-                quote! {
-                    let #v = mva__.take(#k as usize).unwrap().sb_string_to()?;
+            let CodecCode { encoding, reader, writer, nested_text, extended, compact, schema_table, schema_version } =
+                codec_code(&options);
+            let (version_de, version_en) = match options.version {
+                Some(version) => {
+                    let version_str = version.to_string();
+                    (
+                        quote! {
+                            let version = items.item()?;
+                            if version != #version_str {
+                                return Err(::scratchback::encoding::EncodingError::Version {
+                                    expected: #version,
+                                    found: version.to_string(),
+                                });
+                            }
+                        },
+                        quote! { items.item(#version_str)?; },
+                    )
                 }
-            });
-            let items_n = mapped_de_items.len();
-            // let flatten_target = {
-            //     if let Some(t) = flattens_to {
-            //         quote! {
-            //         }
-            //     }
-            // };
+                None => (quote! {}, quote! {}),
+            };
 
             let result =
                 quote! {
                 impl ::scratchback::encoding::ScratchObject for #name {
                     /// Create a new instance of this struct from a `scratchback`-encoded string.
-                    fn from_sb_encoded(numbers: &str) -> Option<Self> {
-                        use ::scratchback::encoding::{ SbStringTo, Encoding };
-                        use ::scratchback::moving;
+                    fn try_from_sb_encoded(numbers: &str) -> Result<Self, ::scratchback::encoding::EncodingError> {
+                        use ::scratchback::encoding::{ ItemReader, ScratchField };
 
-                        let Ok(mut mva__) = moving::nmovable::<_, #items_n>(Encoding::decode_items(numbers)?) else {
-                            return None;
-                        };
-                        #( #mapped_de_items )*
+                        let text = <#encoding>::try_decode(numbers)?;
+                        let mut items = #reader;
+                        #version_de
+                        #read
 
-                        Some(Self {
-                            #(#field_names, )*
+                        Ok(Self {
+                            #(#names, )*
                         })
                     }
                     /// Serialize this struct instance to a `scratchback`-encoded string.
-                    fn sb_encode(self) -> Option<String> {
-                        use ::scratchback::encoding::{ SbToString, Encoding };
-                        
-                        let Self { #(#field_names, )* } = self;
-                        #(
-                            let s = #field_names.sb_to_string();
-                            let #field_names = s.as_str();
-                        )*
-                        let d = [#(#field_names, )*];
-                        Encoding::encode_items(&d)
+                    fn try_sb_encode(self) -> Result<String, ::scratchback::encoding::EncodingError> {
+                        use ::scratchback::encoding::{ ItemWriter, ScratchField };
+
+                        let Self { #(#names, )* } = self;
+                        let mut items = #writer;
+                        #version_en
+                        #write
+
+                        <#encoding>::try_encode(&items.finish())
                     }
+                    #nested_text
+                }
+
+                impl ::scratchback::encoding::ScratchSchema for #name {
+                    const SCHEMA: ::scratchback::encoding::Schema = {
+                        use ::scratchback::encoding::schema::{ Field, Schema, SchemaKind };
+
+                        Schema {
+                            name: ::core::stringify!(#name),
+                            extended: #extended,
+                            compact: #compact,
+                            table: #schema_table,
+                            version: #schema_version,
+                            kind: SchemaKind::Struct {
+                                fields: #schema_fields,
+                                flatten: #schema_flatten,
+                            },
+                        }
+                    };
                 }
             };
             Ok(result.into())
         }
 
         Item::Enum(en) => {
-            let fields = en.variants;
-            let mut map: HashMap<u8, (Ident, TypeExpr)> = HashMap::new();
-
-            for field in fields.items() {
-                for attr in &field.attributes {
-                    let attr_name = &attr.path.last().as_ref().unwrap().to_string();
-                    if attr_name != "id" {
-                        continue;
-                    }
-
-                    let Some(value_token) = attr.get_value_tokens().first() else {
-                        return Err(Error::new_at_span(attr.span(), "Assign an ID: #[id(...)]"));
-                    };
-
-                    let Ok(id) = value_token.to_string().parse::<u8>() else {
-                        return Err(
-                            Error::new_at_span(attr.span(), "Cannot parse into u8 (range: 0-255)")
-                        );
-                    };
+            let options = ok_or_rt!(options(&en.attributes));
+            let CodecCode { encoding, reader, writer, nested_text, extended, compact, schema_table, schema_version } =
+                codec_code(&options);
+            let name = en.name;
+            let mut ids = BTreeSet::new();
+            let mut mapped_en_items = Vec::new();
+            let mut mapped_de_items = Vec::new();
+            // Payloads of tuple and struct variants are handled by functions of their own, so errors from `?`
+            // inside them are still wrapped in `EncodingError::Variant`.
+            let mut encode_fns = Vec::new();
+            let mut decode_fns = Vec::new();
+            let mut schema_variants = Vec::new();
+
+            for variant in en.variants.items() {
+                let attr = variant.attributes.iter().find(|attr| attr.path.last().unwrap().to_string() == "id");
+                let Some(attr) = attr else {
+                    return Err(Error::new_at_span(variant.name.span(), "Assign an ID: #[id(...)]"));
+                };
+                let Some(value_token) = attr.get_value_tokens().first() else {
+                    return Err(Error::new_at_span(attr.span(), "Assign an ID: #[id(...)]"));
+                };
+                let Ok(id) = value_token.to_string().parse::<u8>() else {
+                    return Err(
+                        Error::new_at_span(attr.span(), "Cannot parse into u8 (range: 0-255)")
+                    );
+                };
+                if !ids.insert(id) {
+                    return Err(
+                        Error::new_at_span(value_token.span(), "This id already exists")
+                    );
+                }
 
-                    if map.contains_key(&id) {
-                        return Err(
-                            Error::new_at_span(value_token.span(), "This id already exists")
-                        );
+                let variant_name = &variant.name;
+                let k = id.to_string();
+                let encode_fn = format_ident!("encode_{}", id);
+                let decode_fn = format_ident!("decode_{}", id);
+
+                match &variant.fields {
+                    Fields::Unit => {
+                        mapped_en_items.push(quote! { Self::#variant_name => (Ok(String::new()), #k), });
+                        mapped_de_items.push(quote! { #k => Ok(Self::#variant_name), });
+                        schema_variants.push(quote! {
+                            Variant { id: #id, name: ::core::stringify!(#variant_name), ty: "", fields: &[] }
+                        });
+                    }
+                    // One value keeps its own encoding as the payload.
+                    Fields::Tuple(tuple) if tuple.fields.len() == 1 => {
+                        let (tuple_field, _) = tuple.fields.first().unwrap();
+                        let typ = &tuple_field.ty;
+                        mapped_en_items.push(quote! { Self::#variant_name(x) => (x.try_sb_encode(), #k), });
+                        mapped_de_items.push(quote! {
+                            #k => <#typ as ::scratchback::encoding::ScratchObject>::try_from_sb_encoded(payload)
+                                .map(Self::#variant_name),
+                        });
+                        schema_variants.push(quote! {
+                            Variant {
+                                id: #id,
+                                name: ::core::stringify!(#variant_name),
+                                ty: ::core::stringify!(#typ),
+                                fields: &[],
+                            }
+                        });
+                    }
+                    // More values are elements, like those of a tuple field.
+                    Fields::Tuple(tuple) => {
+                        let types = tuple.fields.items().map(|field| field.ty.clone()).collect::<Vec<_>>();
+                        let bindings = (0..types.len()).map(|idx| format_ident!("x{}", idx)).collect::<Vec<_>>();
+                        let indices = 0..types.len();
+                        let ty = types
+                            .iter()
+                            .map(|typ| typ.to_token_stream().to_string())
+                            .collect::<Vec<_>>()
+                            .join(", ");
+
+                        encode_fns.push(quote! {
+                            fn #encode_fn(#(#bindings: #types, )*) -> Result<String, EncodingError> {
+                                let mut items = #writer;
+                                #( items.element(#indices, #bindings)?; )*
+                                <#encoding>::try_encode(&items.finish())
+                            }
+                        });
+                        let indices = 0..types.len();
+                        decode_fns.push(quote! {
+                            fn #decode_fn(payload: &str) -> Result<#name, EncodingError> {
+                                let text = <#encoding>::try_decode(payload)?;
+                                let mut items = #reader;
+                                Ok(#name::#variant_name(#( items.element::<#types>(#indices)?, )*))
+                            }
+                        });
+                        mapped_en_items.push(quote! {
+                            Self::#variant_name(#(#bindings, )*) => (#encode_fn(#(#bindings, )*), #k),
+                        });
+                        mapped_de_items.push(quote! { #k => #decode_fn(payload), });
+                        schema_variants.push(quote! {
+                            Variant { id: #id, name: ::core::stringify!(#variant_name), ty: #ty, fields: &[] }
+                        });
                     }
+                    // Named fields are items at the position of their id, like those of a struct.
+                    Fields::Named(named) => {
+                        let fields = ok_or_rt!(id_fields(named));
+                        if let Some(flatten) = &fields.flatten {
+                            return Err(Error::new_at_span(flatten.span(), "Only structs can have a flattened field"));
+                        }
+                        let ItemsCode { names, types, read, write, schema_fields, .. } = items_code(&fields);
 
-                    let Fields::Tuple(t) = &field.fields else {
-                        return Err(
-                            Error::new_at_span(field.fields.span(), "Expected tuple-based fields")
-                        );
-                    };
-                    let tuple_fields = &t.fields.inner;
-                    if tuple_fields.len() > 1 {
-                        return Err(
-                            Error::new_at_span(
-                                field.fields.span(),
-                                "Currently, you can only have one item for a tuple field"
-                            )
-                        );
+                        encode_fns.push(quote! {
+                            fn #encode_fn(#(#names: #types, )*) -> Result<String, EncodingError> {
+                                let mut items = #writer;
+                                #write
+                                <#encoding>::try_encode(&items.finish())
+                            }
+                        });
+                        decode_fns.push(quote! {
+                            fn #decode_fn(payload: &str) -> Result<#name, EncodingError> {
+                                let text = <#encoding>::try_decode(payload)?;
+                                let mut items = #reader;
+                                #read
+                                Ok(#name::#variant_name { #(#names, )* })
+                            }
+                        });
+                        mapped_en_items.push(quote! {
+                            Self::#variant_name { #(#names, )* } => (#encode_fn(#(#names, )*), #k),
+                        });
+                        mapped_de_items.push(quote! { #k => #decode_fn(payload), });
+                        schema_variants.push(quote! {
+                            Variant { id: #id, name: ::core::stringify!(#variant_name), ty: "", fields: #schema_fields }
+                        });
                     }
-                    let (tuple_field, _) = tuple_fields.first().unwrap();
-                    map.insert(id, (field.name.clone(), tuple_field.ty.clone()));
                 }
             }
 
-            let name = en.name;
-            let mut mapped_en_items = Vec::new();
-            let mapped_de_items = map.iter().map(|(k, (variant, typ))| {
-                let k = k.to_string();
-
-                mapped_en_items.push(
-                    quote! {
-                        #k => Self::#variant(#typ::from_sb_encoded(&x)?),
-                    }
-                );
-
-                quote! {
-                    Self::#variant(x) => (x, #k), 
+            // `version • id • payload`, with the version checked like that of a struct.
+            let (version_de, version_en) = match options.version {
+                Some(version) => {
+                    let version_str = version.to_string();
+                    (
+                        quote! {
+                            let Some(split_loc) = <#encoding>::find_splitter(numbers) else {
+                                return Err(EncodingError::MissingSplitter);
+                            };
+                            let version = <#encoding>::try_decode(&numbers[..split_loc])?;
+                            if version != #version_str {
+                                return Err(EncodingError::Version { expected: #version, found: version });
+                            }
+                            let start = split_loc + <#encoding>::SPLITTER_ENCODED.len();
+                        },
+                        quote! { ::core::concat!(#version_str, "•") },
+                    )
                 }
-            });
+                None => (quote! { let start = 0; }, quote! { "" }),
+            };
 
             let result =
                 quote! {
                 impl ::scratchback::encoding::ScratchObject for #name {
                     /// Serialize this enum instance to a `scratchback`-encoded string.
-                    fn sb_encode(self) -> Option<String> {
-                        use ::scratchback::encoding::{ Encoding, EncodingTable };
+                    fn try_sb_encode(self) -> Result<String, ::scratchback::encoding::EncodingError> {
+                        use ::scratchback::encoding::{ EncodingError, ItemWriter, ScratchField, ScratchObject };
 
-                        let (st, id) = match self {
-                            #(#mapped_de_items)*
-                        };
+                        #(#encode_fns)*
 
-                        Some(format!("{}{}{}", Encoding::encode(id)?, EncodingTable::encode(Encoding::SPLITTER)?, st.sb_encode()?))
+                        let header = #version_en;
+                        let (payload, id) = match self {
+                            #(#mapped_en_items)*
+                        };
+                        let payload = payload.map_err(|source| EncodingError::Variant {
+                            id: id.to_string(),
+                            offset: header.len() + id.len() + <#encoding>::SPLITTER.len_utf8(),
+                            source: Box::new(source),
+                        })?;
+
+                        let mut numbers = <#encoding>::try_encode(&format!("{header}{id}"))?;
+                        numbers.push_str(<#encoding>::SPLITTER_ENCODED);
+                        numbers.push_str(&payload);
+                        Ok(numbers)
                     }
 
                     /// Create a new instance of this enum from a `scratchback`-encoded string.
-                    fn from_sb_encoded(numbers: &str) -> Option<Self> {
-                        use ::scratchback::encoding::{ Encoding, EncodingTable };
+                    fn try_from_sb_encoded(numbers: &str) -> Result<Self, ::scratchback::encoding::EncodingError> {
+                        use ::scratchback::encoding::{ EncodingError, ItemReader, ScratchField };
 
-                        let split_loc = numbers.find(&Encoding::SPLITTER_ENCODED)?;
-                        let n = Encoding::decode(&numbers[0..split_loc])?;
-                        let x = &numbers[(split_loc + 2)..numbers.len()];
+                        #(#decode_fns)*
 
-                        let res = match n.as_ref() {
-                            #(#mapped_en_items)*
+                        #version_de
+                        let Some(split_loc) = <#encoding>::find_splitter(&numbers[start..]).map(|split| start + split)
+                        else {
+                            return Err(EncodingError::MissingSplitter);
+                        };
+                        let id = <#encoding>::try_decode(&numbers[start..split_loc])?;
+                        let offset = split_loc + <#encoding>::SPLITTER_ENCODED.len();
+                        let payload = &numbers[offset..];
+
+                        let res = match id.as_str() {
+                            #(#mapped_de_items)*
                             _ => {
-                                return None;
+                                return Err(EncodingError::UnknownVariant { id });
                             }
                         };
-                        Some(res)
+                        res.map_err(|source| EncodingError::Variant { id, offset, source: Box::new(source) })
                     }
+                    #nested_text
+                }
+
+                impl ::scratchback::encoding::ScratchSchema for #name {
+                    const SCHEMA: ::scratchback::encoding::Schema = {
+                        use ::scratchback::encoding::schema::{ Field, Schema, SchemaKind, Variant };
+
+                        Schema {
+                            name: ::core::stringify!(#name),
+                            extended: #extended,
+                            compact: #compact,
+                            table: #schema_table,
+                            version: #schema_version,
+                            kind: SchemaKind::Enum {
+                                variants: &[#(#schema_variants, )*],
+                            },
+                        }
+                    };
                 }
             };
 
@@ -292,7 +737,29 @@ fn derive(input: TokenStream) -> Result<TokenStream, Error> {
 
 /// Marks a `struct` as a Scratch object.
 ///
-/// Zero-indexed.
+/// Zero-indexed. Add `#[scratch(extended)]` to the struct to encode characters outside the encoding table
+/// with `ExtendedEncoding`, or `#[scratch(compact)]` to fit longer text in a cloud variable with
+/// `CompactEncoding`. A project with its own table, made with `encoding_table!`, can use it with
+/// `#[scratch(table = MyTable)]`.
+///
+/// Fields can be strings, numbers and booleans, other `ScratchObject`s, and `Vec`, `Option`, arrays and tuples
+/// of any of those (see `scratchback::encoding::field`).
+///
+/// To add fields without breaking projects that still send the old layout, give them the next ids and
+/// `#[id(n, default)]` or `#[id(n, default = expr)]`; `Option` fields are `None` when missing. Items after the
+/// last id are ignored, so older decoders read newer values too.
+///
+/// `#[scratch(version = n)]` writes `n` as the first item (before the variant id of an enum), and values with
+/// any other version fail to decode with `EncodingError::Version`. `Schema::read_version` tells which type to
+/// decode a value with, so several versions can be read during a rollout. Adding or removing the version is
+/// itself a breaking change of the layout: decoders without it read the version as their first field, and
+/// decoders with it reject values without one.
+///
+/// Every variant of an enum needs an `#[id(n)]` and is encoded as `id • payload`. The payload of a one-value
+/// tuple variant is that value's own encoding, so it must be a `ScratchObject`; longer tuple variants and struct
+/// variants, whose fields take `#[id]`s like those of a struct, lay their values out as items. Unit variants
+/// have an empty payload. `#[scratch(...)]` works on enums too: the id, the splitter and the items of the
+/// payload use the encoding it chooses, while a one-value payload keeps that of its own type.
 ///
 /// ```no_run
 /// #[derive(ScratchObject)]
@@ -303,7 +770,7 @@ fn derive(input: TokenStream) -> Result<TokenStream, Error> {
 ///
 /// Player::from_sb_encoded("...");
 /// ```
-#[proc_macro_derive(ScratchObject, attributes(id))]
+#[proc_macro_derive(ScratchObject, attributes(id, scratch))]
 pub fn derive_scratch(input: TokenStream) -> TokenStream {
     let res = derive(input);
     res.unwrap_or_else(|err| err.to_compile_error().into()).into()
@@ -1,94 +1,1102 @@
-use std::sync::Arc;
+#[cfg(feature = "server")]
+pub mod server;
 
-use serde::{ Deserialize, Serialize };
+use std::{
+    collections::{ HashMap, VecDeque },
+    hash::{ BuildHasher, Hasher, RandomState },
+    sync::{ Arc, Mutex as StdMutex },
+    time::{ Duration, Instant },
+};
 
-use futures_util::{ stream::{ SplitSink, SplitStream }, SinkExt, StreamExt };
-use tokio::{ net::TcpStream, sync::Mutex };
-use tokio_tungstenite::{ tungstenite::Message, MaybeTlsStream, WebSocketStream };
+use serde::{ Deserialize, Deserializer, Serialize, Serializer };
 
-const ENDPOINT: &'static str = "wss://clouddata.scratch.mit.edu/";
+use futures_util::{ stream::BoxStream, SinkExt, Stream, StreamExt };
+use tokio::{ net::TcpStream, sync::{ broadcast, mpsc, oneshot, watch, Mutex } };
+use tokio_tungstenite::{
+    tungstenite::{ self, client::IntoClientRequest, http::{ HeaderName, HeaderValue }, Message },
+    MaybeTlsStream,
+    WebSocketStream,
+};
+
+#[cfg(any(feature = "native-tls", feature = "rustls"))]
+pub use tokio_tungstenite::Connector;
+
+use crate::{ encoding::Codec, session::Session, ErrorKind };
+
+/// The cloud server of `scratch.mit.edu`.
+pub const ENDPOINT: &'static str = "wss://clouddata.scratch.mit.edu/";
+/// The cloud server of TurboWarp.
+pub const TURBOWARP_ENDPOINT: &str = "wss://clouddata.turbowarp.org/";
+const ORIGIN: &str = "https://scratch.mit.edu";
+const USER_AGENT: &str = concat!("scratchback/", env!("CARGO_PKG_VERSION"));
 const CLOUD: &'static str = "☁ ";
 
-type Tx = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>;
-type Rx = SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>;
+/// How many incoming frames are buffered before the oldest are dropped.
+const INCOMING_CAPACITY: usize = 1024;
+const EVENTS_CAPACITY: usize = 64;
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
 
 #[derive(Debug, thiserror::Error)]
 pub enum SendError {
-    #[error("WebSocket error: {0:?}")] WebSocket(tokio_tungstenite::tungstenite::Error),
+    #[error("WebSocket error: {0:?}")] WebSocket(tungstenite::Error),
     #[error("Failed to serialize: {0:#?}")] Serializing(serde_json::Error),
+    #[error("Connection closed")] Closed,
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum NextError {
-    #[error("WebSocket error: {0:?}")] WebSocket(tokio_tungstenite::tungstenite::Error),
+    #[error("WebSocket error: {0:?}")] WebSocket(Arc<tungstenite::Error>),
     #[error("Failed to convert to text")] ToText,
     #[error("Failed to deserialize: {0:#?}")] Deserializing(serde_json::Error),
+    #[error("Skipped {0} messages that were not read in time")] Lagged(u64),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SetError {
+    #[error("Cloud values can only contain numbers")] NotNumeric,
+    #[error("Value is {length} characters long, the limit is {max}")] TooLong {
+        length: usize,
+        max: usize,
+    },
+    #[error("{0}")] Send(Box<SendError>),
+}
+
+impl From<SendError> for SetError {
+    fn from(err: SendError) -> Self {
+        Self::Send(Box::new(err))
+    }
+}
+
+/// What a cloud server accepts as a value, see [`CloudBuilder::limits`].
+#[derive(Debug, Clone)]
+pub struct CloudLimits {
+    /// Maximum length of a value, in characters.
+    pub max_length: usize,
+    /// Only accept numbers such as `-12.5` or `1e21`.
+    pub numeric_only: bool,
+}
+
+impl CloudLimits {
+    pub const SCRATCH: Self = Self { max_length: 256, numeric_only: true };
+    pub const TURBOWARP: Self = Self { max_length: 10240, numeric_only: true };
+
+    /// Check a value against these limits. The empty string is allowed.
+    pub fn check(&self, value: &str) -> Result<(), SetError> {
+        if self.numeric_only && !value.is_empty() && !is_numeric(value) {
+            return Err(SetError::NotNumeric);
+        }
+        let length = value.chars().count();
+        if length > self.max_length {
+            return Err(SetError::TooLong { length, max: self.max_length });
+        }
+        Ok(())
+    }
+
+    /// Split a payload encoded with `codec` into values within the length limit, never splitting a code.
+    pub fn chunks<'a>(&self, payload: &'a str, codec: Codec) -> Result<Vec<&'a str>, SetError> {
+        if !payload.is_ascii() {
+            return Err(SetError::NotNumeric);
+        }
+
+        let mut chunks = Vec::new();
+        let mut rest = payload;
+        while !rest.is_empty() {
+            let mut size = 0;
+            while size < rest.len() {
+                let width = codec.code_width(&rest[size..]);
+                if size + width > self.max_length {
+                    break;
+                }
+                size += width;
+            }
+            if size == 0 {
+                return Err(SetError::TooLong { length: payload.len(), max: self.max_length });
+            }
+            let (chunk, tail) = rest.split_at(size);
+            chunks.push(chunk);
+            rest = tail;
+        }
+        Ok(chunks)
+    }
+
+    /// Split a payload across `vars` variables, checking every chunk before any is sent.
+    fn chunks_for<'a>(&self, vars: usize, payload: &'a str, codec: Codec) -> Result<Vec<&'a str>, SetError> {
+        // A split can land anywhere, so only plain digits are still numbers afterwards.
+        if self.numeric_only && !payload.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(SetError::NotNumeric);
+        }
+        let chunks = self.chunks(payload, codec)?;
+        if chunks.len() > vars {
+            return Err(SetError::TooLong { length: payload.len(), max: vars * self.max_length });
+        }
+        for chunk in &chunks {
+            self.check(chunk)?;
+        }
+        Ok(chunks)
+    }
+}
+
+impl Default for CloudLimits {
+    fn default() -> Self {
+        Self::SCRATCH
+    }
+}
+
+/// `-?digits(.digits)?(e[+-]?digits)?`
+fn is_numeric(value: &str) -> bool {
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+
+    let value = value.strip_prefix('-').unwrap_or(value);
+    let (mantissa, exponent) = match value.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+        None => (value, None),
+    };
+    let mantissa_ok = match mantissa.split_once('.') {
+        Some((whole, fraction)) => digits(whole) && digits(fraction),
+        None => digits(mantissa),
+    };
+    let exponent_ok = exponent.is_none_or(|exponent| {
+        digits(exponent.strip_prefix(['+', '-']).unwrap_or(exponent))
+    });
+
+    mantissa_ok && exponent_ok
+}
+
+/// Backoff settings for a reconnecting [`Cloud`].
+///
+/// The delay before attempt `n` is `initial_delay * multiplier^(n - 1)`, capped at
+/// `max_delay`, of which up to `jitter` (a fraction) is randomly subtracted.
+#[derive(Debug, Clone)]
+pub struct Reconnect {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub multiplier: f64,
+    /// Fraction of the delay that is randomized, from `0.0` to `1.0`.
+    pub jitter: f64,
+    /// Give up after this many consecutive failed attempts. `None` retries forever.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for Reconnect {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.2,
+            max_attempts: None,
+        }
+    }
+}
+
+impl Reconnect {
+    /// The delay to wait before the given (one-indexed) attempt.
+    ///
+    /// A NaN `multiplier` waits `max_delay` and a NaN `jitter` is no jitter. Delays too long for a
+    /// `Duration` are `max_delay`.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exp = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        // `min` ignores NaN, and the delay can be negative with a negative multiplier.
+        let base = (self.initial_delay.as_secs_f64() * self.multiplier.powi(exp))
+            .min(self.max_delay.as_secs_f64())
+            .max(0.0);
+        let jitter = if self.jitter.is_nan() { 0.0 } else { self.jitter.clamp(0.0, 1.0) * random_unit() };
+        Duration::try_from_secs_f64(base * (1.0 - jitter))
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay))
+    }
+}
+
+/// A random number in `0.0..1.0`, good enough for jitter.
+pub(crate) fn random_unit() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+    ((bits >> 11) as f64) / ((1_u64 << 53) as f64)
+}
+
+/// A token bucket limiting how fast messages are written, see [`CloudBuilder::rate_limit`].
+///
+/// Handshakes are not limited. The Scratch server disconnects clients that set variables much faster
+/// than about 10 times a second.
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    /// Messages per second on average.
+    pub per_second: f64,
+    /// How many messages may be written back to back after being idle.
+    pub burst: u32,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self { per_second: 10.0, burst: 5 }
+    }
+}
+
+impl RateLimit {
+    /// The slowest rate [`CloudBuilder::rate_limit`] accepts, one message an hour.
+    pub const MIN_PER_SECOND: f64 = 1.0 / 3600.0;
+
+    /// Raise a rate that is zero, negative or NaN to [`Self::MIN_PER_SECOND`]. An infinite rate is no limit.
+    fn clamped(self) -> Option<Self> {
+        if self.per_second == f64::INFINITY {
+            return None;
+        }
+        let per_second = if self.per_second >= Self::MIN_PER_SECOND {
+            self.per_second
+        } else {
+            Self::MIN_PER_SECOND
+        };
+        Some(Self { per_second, ..self })
+    }
+}
+
+struct Bucket {
+    limit: RateLimit,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    fn new(limit: RateLimit) -> Self {
+        Self { tokens: limit.burst as f64, refilled: Instant::now(), limit }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let earned = now.duration_since(self.refilled).as_secs_f64() * self.limit.per_second;
+        self.tokens = (self.tokens + earned).min(self.limit.burst.max(1) as f64);
+        self.refilled = now;
+    }
+
+    fn try_take(&mut self) -> bool {
+        self.refill();
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// How long until the next token.
+    fn wait(&self) -> Duration {
+        Duration::from_secs_f64(((1.0 - self.tokens) / self.limit.per_second).max(0.0))
+    }
+}
+
+/// Changes of the underlying connection, see [`Cloud::events`].
+#[derive(Debug, Clone)]
+pub enum ConnectionEvent {
+    /// The connection dropped, with the WebSocket error if there was one.
+    Disconnected {
+        reason: Option<Arc<tungstenite::Error>>,
+    },
+    /// A reconnect attempt will be made after `delay`.
+    Reconnecting {
+        attempt: u32,
+        delay: Duration,
+    },
+    /// The connection was re-established; projects have re-handshaked and queued messages were replayed.
+    Connected,
+    /// Reconnecting was abandoned after [`Reconnect::max_attempts`].
+    GaveUp,
+}
+
+/// Something that happened on a cloud connection, see [`Cloud::stream`] and [`CloudProject::stream`].
+#[derive(Debug, Clone)]
+pub enum CloudEvent {
+    Set {
+        project_id: Option<String>,
+        user: Option<String>,
+        name: String,
+        value: String,
+    },
+    Created {
+        project_id: Option<String>,
+        user: Option<String>,
+        name: String,
+        value: String,
+    },
+    Deleted {
+        project_id: Option<String>,
+        user: Option<String>,
+        name: String,
+    },
+    Renamed {
+        project_id: Option<String>,
+        user: Option<String>,
+        name: String,
+        new_name: String,
+    },
+    /// Any other message, such as methods this crate does not know.
+    Other(CloudMethod),
+    Connection(ConnectionEvent),
+}
+
+impl CloudEvent {
+    /// The project this event is about. `None` for connection events, which concern every project.
+    pub fn project_id(&self) -> Option<&str> {
+        match self {
+            | Self::Set { project_id, .. }
+            | Self::Created { project_id, .. }
+            | Self::Deleted { project_id, .. }
+            | Self::Renamed { project_id, .. } => project_id.as_deref(),
+            Self::Other(method) => method.project_id(),
+            Self::Connection(_) => None,
+        }
+    }
+
+    /// The variable this event is about, with the `☁ ` prefix. For renames, the old name.
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            | Self::Set { name, .. }
+            | Self::Created { name, .. }
+            | Self::Deleted { name, .. }
+            | Self::Renamed { name, .. } => Some(name),
+            Self::Other(_) | Self::Connection(_) => None,
+        }
+    }
+}
+
+impl From<CloudMethod> for CloudEvent {
+    fn from(method: CloudMethod) -> Self {
+        match method {
+            CloudMethod::Set { name, user, project_id, value } => Self::Set { project_id, user, name, value },
+            CloudMethod::Create { name, user, project_id, value } => {
+                Self::Created { project_id, user, name, value }
+            }
+            CloudMethod::Delete { name, user, project_id } => Self::Deleted { project_id, user, name },
+            CloudMethod::Rename { name, new_name, user, project_id } => {
+                Self::Renamed { project_id, user, name, new_name }
+            }
+            other => Self::Other(other),
+        }
+    }
+}
+
+/// A [`Stream`] of [`CloudEvent`]s.
+///
+/// Every stream receives its own copy of the events that happen after it was created, so streams can be
+/// consumed (and combined with `StreamExt`) independently of each other and of `next`.
+pub struct CloudEvents {
+    inner: BoxStream<'static, Result<CloudEvent, NextError>>,
+}
+
+impl CloudEvents {
+    fn new(incoming: broadcast::Receiver<Frame>) -> Self {
+        let inner = futures_util::stream::unfold(incoming, |mut incoming| async move {
+            let item = match incoming.recv().await {
+                Ok(frame) => frame.into_event(),
+                Err(broadcast::error::RecvError::Closed) => {
+                    return None;
+                }
+                Err(broadcast::error::RecvError::Lagged(n)) => Err(NextError::Lagged(n)),
+            };
+            Some((item, incoming))
+        });
+        Self { inner: inner.boxed() }
+    }
+}
+
+impl Stream for CloudEvents {
+    type Item = Result<CloudEvent, NextError>;
+
+    fn poll_next(
+        mut self: core::pin::Pin<&mut Self>,
+        cx: &mut core::task::Context<'_>
+    ) -> core::task::Poll<Option<Self::Item>> {
+        self.inner.poll_next_unpin(cx)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Frame {
+    Method(CloudMethod),
+    /// A line that failed to deserialize.
+    Invalid(String),
+    NotText,
+    Error(Arc<tungstenite::Error>),
+    Connection(ConnectionEvent),
+}
+
+impl Frame {
+    fn into_event(self) -> Result<CloudEvent, NextError> {
+        match self {
+            Frame::Method(method) => Ok(method.into()),
+            Frame::Invalid(s) => {
+                let binding = serde_json::from_str::<CloudMethod>(&s);
+                binding.map(CloudEvent::from).map_err(NextError::Deserializing)
+            }
+            Frame::NotText => Err(NextError::ToText),
+            Frame::Error(err) => Err(NextError::WebSocket(err)),
+            Frame::Connection(event) => Ok(CloudEvent::Connection(event)),
+        }
+    }
+}
+
+enum Kind {
+    /// Remembered and re-sent for this project after every reconnect.
+    Handshake(String),
+    /// A change made by a [`CloudProject`], applied to its mirror once written.
+    Change(CloudMethod),
+    Message,
+}
+
+struct Outgoing {
+    text: String,
+    kind: Kind,
+    /// More than one when sets were coalesced.
+    done: Vec<oneshot::Sender<Result<(), SendError>>>,
+}
+
+impl Outgoing {
+    /// The project and variable of a set, which later sets may replace.
+    fn set_key(&self) -> Option<(&str, &str)> {
+        match &self.kind {
+            Kind::Change(CloudMethod::Set { name, project_id, .. }) => Some((project_id.as_deref()?, name)),
+            _ => None,
+        }
+    }
+
+    fn fail(self, err: tungstenite::Error) {
+        let mut done = self.done.into_iter();
+        if let Some(first) = done.next() {
+            let _ = first.send(Err(SendError::WebSocket(err)));
+        }
+        for rest in done {
+            let _ = rest.send(Err(SendError::Closed));
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct Cloud {
-    tx: Arc<Mutex<Tx>>,
-    rx: Arc<Mutex<Rx>>,
+    outgoing: mpsc::UnboundedSender<Outgoing>,
+    incoming: Arc<Mutex<broadcast::Receiver<Frame>>>,
+    events: broadcast::Sender<ConnectionEvent>,
+    routes: SharedRoutes,
+    limits: CloudLimits,
     user: String,
 }
 
+/// Where the driver sends frames, shared with the [`Cloud`] handles and [`CloudProject`]s.
+struct Routes {
+    /// Every frame. `None` once the driver stopped, which ends every receiver.
+    all: Option<broadcast::Sender<Frame>>,
+    /// Frames by project id.
+    projects: HashMap<String, broadcast::Sender<Frame>>,
+    /// Mirrored variables by project id.
+    variables: HashMap<String, Arc<Variables>>,
+}
+
+type SharedRoutes = Arc<StdMutex<Routes>>;
+
+impl Routes {
+    fn new(all: broadcast::Sender<Frame>) -> Self {
+        Self { all: Some(all), projects: HashMap::new(), variables: HashMap::new() }
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<Frame> {
+        match &self.all {
+            Some(all) => all.subscribe(),
+            None => broadcast::channel(1).1,
+        }
+    }
+
+    fn subscribe_project(&mut self, id: &str) -> broadcast::Receiver<Frame> {
+        if self.all.is_none() {
+            return broadcast::channel(1).1;
+        }
+        self.projects
+            .entry(id.to_string())
+            .or_insert_with(|| broadcast::channel(INCOMING_CAPACITY).0)
+            .subscribe()
+    }
+
+    fn variables(&mut self, id: &str) -> Arc<Variables> {
+        self.variables.entry(id.to_string()).or_default().clone()
+    }
+
+    /// Drop every sender so receivers see the end of the stream.
+    fn close(&mut self) {
+        self.all = None;
+        self.projects.clear();
+    }
+}
+
+/// Receive the next message for `next`, skipping connection events.
+async fn recv(incoming: &Mutex<broadcast::Receiver<Frame>>) -> Option<Result<CloudMethod, NextError>> {
+    let mut stream = incoming.lock().await;
+    loop {
+        let frame = match stream.recv().await {
+            Ok(frame) => frame,
+            Err(broadcast::error::RecvError::Closed) => {
+                return None;
+            }
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                return Some(Err(NextError::Lagged(n)));
+            }
+        };
+
+        return match frame {
+            Frame::Method(method) => Some(Ok(method)),
+            Frame::Invalid(s) => {
+                let binding = serde_json::from_str::<CloudMethod>(&s);
+                Some(binding.map_err(NextError::Deserializing))
+            }
+            Frame::NotText => Some(Err(NextError::ToText)),
+            Frame::Error(err) => Some(Err(NextError::WebSocket(err))),
+            Frame::Connection(_) => {
+                continue;
+            }
+        };
+    }
+}
+
 impl Cloud {
-    pub async fn connect(username: String) -> Result<Self, Box<dyn core::error::Error>> {
-        let (stream, _) = tokio_tungstenite::connect_async(ENDPOINT).await?;
-        let (tx, rx) = stream.split();
-        Ok(Self {
-            tx: Arc::new(Mutex::new(tx)),
-            rx: Arc::new(Mutex::new(rx)),
-            user: username,
-        })
+    pub async fn connect(username: String) -> Result<Self, crate::Error> {
+        Self::builder(username).connect().await
+    }
+
+    /// Connect in reconnecting mode.
+    ///
+    /// When the connection drops, it is re-established with the given backoff, every project that has
+    /// handshaked re-handshakes, and messages sent in the meantime are replayed in order.
+    pub async fn connect_reconnecting(
+        username: String,
+        reconnect: Reconnect
+    ) -> Result<Self, crate::Error> {
+        Self::builder(username).reconnect(reconnect).connect().await
+    }
+
+    /// Configure the endpoint, headers and connection options before connecting.
+    pub fn builder(username: String) -> CloudBuilder {
+        CloudBuilder::new(username)
     }
 
     /// Send a model to the server.
+    ///
+    /// In reconnecting mode, this waits until the model has been written, replaying it after a reconnect if needed.
     pub async fn send<S: Serialize>(&self, model: &S) -> Result<(), SendError> {
-        let mut sink = self.tx.lock().await;
         let binding = serde_json::to_string(model);
         let Ok(serialized) = binding else {
             return Err(SendError::Serializing(binding.unwrap_err()));
         };
 
-        let res = sink.send(Message::text(serialized)).await;
-        if let Err(err) = res {
-            return Err(SendError::WebSocket(err));
+        self.dispatch(serialized, Kind::Message).await
+    }
+
+    /// Send several models in one frame, one per line.
+    pub async fn send_batch<S: Serialize>(&self, models: &[S]) -> Result<(), SendError> {
+        let mut lines = Vec::with_capacity(models.len());
+        for model in models {
+            let binding = serde_json::to_string(model);
+            let Ok(serialized) = binding else {
+                return Err(SendError::Serializing(binding.unwrap_err()));
+            };
+            lines.push(serialized);
         }
-        Ok(())
+
+        self.dispatch(lines.join("\n"), Kind::Message).await
+    }
+
+    async fn dispatch(&self, text: String, kind: Kind) -> Result<(), SendError> {
+        let (done, ack) = oneshot::channel();
+        if self.outgoing.send(Outgoing { text, kind, done: vec![done] }).is_err() {
+            return Err(SendError::Closed);
+        }
+        ack.await.unwrap_or(Err(SendError::Closed))
     }
 
-    /// Next item.
+    /// Next item, of any project.
     /// This is from the cloud server, therefore the arm `CloudMethod::Handshake` can be marked `unreachable!()`.
+    ///
+    /// Frames holding several messages are split up and yielded in order; a message that fails to
+    /// deserialize does not affect the others. Use [`CloudProject::next`] for the items of a single project.
     pub async fn next(&self) -> Option<Result<CloudMethod, NextError>> {
-        let mut stream = self.rx.lock().await;
-        let Some(res) = stream.next().await else {
-            return None;
-        };
-        let Ok(data) = res else {
-            return Some(Err(NextError::WebSocket(res.unwrap_err())));
-        };
+        recv(&self.incoming).await
+    }
+
+    /// A stream of the events of every project, and of the connection.
+    pub fn stream(&self) -> CloudEvents {
+        CloudEvents::new(self.routes.lock().unwrap().subscribe())
+    }
+
+    /// Subscribe to connection changes.
+    ///
+    /// Only events that happen after subscribing are received.
+    pub fn events(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn project(&self, id: String) -> CloudProject {
+        let mut routes = self.routes.lock().unwrap();
+        let variables = routes.variables(&id);
+        let incoming = Mutex::new(routes.subscribe_project(&id));
+        drop(routes);
+
+        CloudProject { id, cloud: self.clone(), variables, incoming }
+    }
+}
+
+/// Builds a [`Cloud`] connection.
+///
+/// ```no_run
+/// # async fn run() -> Result<(), Box<dyn core::error::Error>> {
+/// use scratchback::{ cloud::{ Cloud, TURBOWARP_ENDPOINT }, session::Session };
+///
+/// let cloud = Cloud::builder("griffpatch".to_string())
+///     .endpoint(TURBOWARP_ENDPOINT)
+///     .user_agent("my-bot/1.0 (contact: me@example.com)")
+///     .session(&Session::from_id("...".to_string()))
+///     .connect()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct CloudBuilder {
+    user: String,
+    dialer: Dialer,
+    reconnect: Option<Reconnect>,
+    rate_limit: Option<RateLimit>,
+    coalesce: bool,
+    limits: CloudLimits,
+}
 
-        let Ok(s) = data.to_text() else {
-            return Some(Err(NextError::ToText));
+impl CloudBuilder {
+    /// Defaults to [`ENDPOINT`] with the `Origin` and `User-Agent` headers of `scratch.mit.edu`.
+    pub fn new(username: String) -> Self {
+        Self {
+            user: username,
+            dialer: Dialer {
+                endpoint: ENDPOINT.to_string(),
+                headers: vec![
+                    ("Origin".to_string(), ORIGIN.to_string()),
+                    ("User-Agent".to_string(), USER_AGENT.to_string())
+                ],
+                connect_timeout: None,
+                #[cfg(any(feature = "native-tls", feature = "rustls"))]
+                connector: None,
+            },
+            reconnect: None,
+            rate_limit: None,
+            coalesce: false,
+            limits: CloudLimits::SCRATCH,
+        }
+    }
+
+    /// The WebSocket URL of the cloud server, e.g. [`TURBOWARP_ENDPOINT`] or a self-hosted server.
+    pub fn endpoint<S: Into<String>>(mut self, url: S) -> Self {
+        self.dialer.endpoint = url.into();
+        self
+    }
+
+    /// Set a header sent with the WebSocket handshake, replacing any earlier value.
+    pub fn header<K: Into<String>, V: Into<String>>(mut self, name: K, value: V) -> Self {
+        let name = name.into();
+        self.dialer.headers.retain(|(k, _)| !k.eq_ignore_ascii_case(&name));
+        self.dialer.headers.push((name, value.into()));
+        self
+    }
+
+    pub fn origin<S: Into<String>>(self, origin: S) -> Self {
+        self.header("Origin", origin)
+    }
+
+    pub fn user_agent<S: Into<String>>(self, user_agent: S) -> Self {
+        self.header("User-Agent", user_agent)
+    }
+
+    /// Authenticate with the session cookie, which the Scratch server requires for writes.
+    pub fn session(self, session: &Session) -> Self {
+        self.header("Cookie", session.cookie())
+    }
+
+    /// Fail connecting (and each reconnect attempt) after this long.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.dialer.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Connect in reconnecting mode, see [`Cloud::connect_reconnecting`].
+    pub fn reconnect(mut self, reconnect: Reconnect) -> Self {
+        self.reconnect = Some(reconnect);
+        self
+    }
+
+    /// Limit how fast messages are written. Messages over the limit wait in order.
+    ///
+    /// A rate below [`RateLimit::MIN_PER_SECOND`] (or NaN) is raised to it; an infinite rate removes the limit.
+    pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limit = limit.clamped();
+        self
+    }
+
+    /// Collapse sets to the same variable that are still waiting to be written into the latest one.
+    ///
+    /// Sets wait when they are over the [rate limit](Self::rate_limit) or while reconnecting. The
+    /// replaced sets complete once the latest value is written.
+    pub fn coalesce(mut self, coalesce: bool) -> Self {
+        self.coalesce = coalesce;
+        self
+    }
+
+    /// What the server accepts as a value, checked before setting. Defaults to [`CloudLimits::SCRATCH`].
+    pub fn limits(mut self, limits: CloudLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Use this TLS connector for `wss://` endpoints instead of the default one.
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    pub fn tls_connector(mut self, connector: Connector) -> Self {
+        self.dialer.connector = Some(connector);
+        self
+    }
+
+    pub async fn connect(self) -> Result<Cloud, crate::Error> {
+        let socket = self.dialer.dial().await?;
+        let (outgoing, outgoing_rx) = mpsc::unbounded_channel();
+        let (incoming, incoming_rx) = broadcast::channel(INCOMING_CAPACITY);
+        let (events, _) = broadcast::channel(EVENTS_CAPACITY);
+        let routes = Arc::new(StdMutex::new(Routes::new(incoming)));
+
+        let driver = Driver {
+            dialer: self.dialer,
+            reconnect: self.reconnect,
+            bucket: self.rate_limit.map(Bucket::new),
+            coalesce: self.coalesce,
+            outgoing: outgoing_rx,
+            events: events.clone(),
+            routes: routes.clone(),
+            projects: Vec::new(),
+            queue: VecDeque::new(),
         };
+        tokio::spawn(driver.run(socket));
 
-        let binding = serde_json::from_str::<CloudMethod>(s);
-        let Ok(method) = binding else {
-            return Some(Err(NextError::Deserializing(binding.unwrap_err())));
+        Ok(Cloud {
+            outgoing,
+            incoming: Arc::new(Mutex::new(incoming_rx)),
+            events,
+            routes,
+            limits: self.limits,
+            user: self.user,
+        })
+    }
+}
+
+/// Everything needed to open (and re-open) the socket.
+struct Dialer {
+    endpoint: String,
+    headers: Vec<(String, String)>,
+    connect_timeout: Option<Duration>,
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    connector: Option<Connector>,
+}
+
+impl Dialer {
+    async fn dial(&self) -> Result<Socket, crate::Error> {
+        let mut request = self.endpoint.as_str().into_client_request()?;
+        for (name, value) in &self.headers {
+            let name = HeaderName::try_from(name).map_err(|err| crate::Error::new(ErrorKind::Protocol, err))?;
+            let value = HeaderValue::try_from(value).map_err(|err| crate::Error::new(ErrorKind::Protocol, err))?;
+            request.headers_mut().insert(name, value);
+        }
+
+        #[cfg(any(feature = "native-tls", feature = "rustls"))]
+        let connecting = tokio_tungstenite::connect_async_tls_with_config(
+            request,
+            None,
+            false,
+            self.connector.clone()
+        );
+        #[cfg(not(any(feature = "native-tls", feature = "rustls")))]
+        let connecting = tokio_tungstenite::connect_async(request);
+
+        let (socket, _) = match self.connect_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, connecting).await??,
+            None => connecting.await?,
         };
+        Ok(socket)
+    }
+}
+
+/// Owns the socket on behalf of every [`Cloud`] handle.
+struct Driver {
+    dialer: Dialer,
+    reconnect: Option<Reconnect>,
+    bucket: Option<Bucket>,
+    coalesce: bool,
+    outgoing: mpsc::UnboundedReceiver<Outgoing>,
+    events: broadcast::Sender<ConnectionEvent>,
+    routes: SharedRoutes,
+    /// Handshakes to repeat after reconnecting, by project id, the latest last.
+    projects: Vec<(String, String)>,
+    /// Messages waiting for the rate limit or for the connection to come back.
+    queue: VecDeque<Outgoing>,
+}
 
-        Some(Ok(method))
+enum Interrupted {
+    /// Every `Cloud` handle was dropped.
+    Released,
+    Dropped(Option<Arc<tungstenite::Error>>),
+}
+
+impl Driver {
+    async fn run(mut self, socket: Socket) {
+        self.drive(socket).await;
+        self.routes.lock().unwrap().close();
     }
 
-    pub fn project(&self, id: String) -> CloudProject {
-        CloudProject { id, cloud: self.clone() }
+    async fn drive(&mut self, mut socket: Socket) {
+        loop {
+            let reason = match self.pump(&mut socket).await {
+                Interrupted::Released => {
+                    let _ = socket.close(None).await;
+                    return;
+                }
+                Interrupted::Dropped(reason) => reason,
+            };
+
+            self.announce(ConnectionEvent::Disconnected { reason: reason.clone() });
+            let Some(reconnect) = self.reconnect.clone() else {
+                if let Some(err) = reason {
+                    self.route(Frame::Error(err));
+                }
+                return;
+            };
+
+            match self.reestablish(&reconnect).await {
+                Some(new_socket) => {
+                    socket = new_socket;
+                }
+                None => {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Forward frames and messages until the connection is interrupted.
+    async fn pump(&mut self, socket: &mut Socket) -> Interrupted {
+        loop {
+            if let Err(err) = self.flush(socket).await {
+                return Interrupted::Dropped(err.map(Arc::new));
+            }
+            let wait = self.bucket.as_ref().map_or(Duration::ZERO, Bucket::wait);
+
+            tokio::select! {
+                frame = socket.next() => match frame {
+                    Some(Ok(Message::Text(text))) => {
+                        self.receive(&text);
+                    }
+                    Some(Ok(Message::Binary(data))) => match core::str::from_utf8(&data) {
+                        Ok(text) => self.receive(text),
+                        Err(_) => {
+                            self.route(Frame::NotText);
+                        }
+                    },
+                    Some(Ok(Message::Close(_))) | None => {
+                        return Interrupted::Dropped(None);
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(err)) => {
+                        return Interrupted::Dropped(Some(Arc::new(err)));
+                    }
+                },
+                out = self.outgoing.recv() => {
+                    let Some(out) = out else {
+                        return Interrupted::Released;
+                    };
+                    self.enqueue(out);
+                }
+                _ = tokio::time::sleep(wait), if !self.queue.is_empty() => {}
+            }
+        }
+    }
+
+    /// Queue a message, replacing a waiting set to the same variable when coalescing.
+    fn enqueue(&mut self, out: Outgoing) {
+        if
+            self.coalesce &&
+            let Some(key) = out.set_key() &&
+            let Some(waiting) = self.queue.iter_mut().find(|waiting| waiting.set_key() == Some(key))
+        {
+            waiting.text = out.text;
+            waiting.kind = out.kind;
+            waiting.done.extend(out.done);
+            return;
+        }
+        self.queue.push_back(out);
+    }
+
+    /// Write queued messages while the rate limit allows.
+    ///
+    /// On failure, the message stays queued in reconnecting mode; the error is `None` if it was handed to the sender instead.
+    async fn flush(&mut self, socket: &mut Socket) -> Result<(), Option<tungstenite::Error>> {
+        while let Some(out) = self.queue.front() {
+            let limited = !matches!(out.kind, Kind::Handshake(_));
+            if limited && self.bucket.as_mut().is_some_and(|bucket| !bucket.try_take()) {
+                break;
+            }
+
+            let out = self.queue.pop_front().unwrap();
+            if let Err(err) = socket.send(Message::text(out.text.as_str())).await {
+                if self.reconnect.is_some() {
+                    self.queue.push_front(out);
+                    return Err(Some(err));
+                }
+                out.fail(err);
+                return Err(None);
+            }
+            self.sent(out);
+        }
+        Ok(())
+    }
+
+    /// The server batches messages into one frame, one JSON object per line.
+    fn receive(&self, text: &str) {
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let frame = match serde_json::from_str::<CloudMethod>(line) {
+                Ok(method) => Frame::Method(method),
+                Err(_) => Frame::Invalid(line.to_string()),
+            };
+            self.route(frame);
+        }
+    }
+
+    /// Hand a frame to its project, mirroring the variables, and to [`Cloud::next`].
+    ///
+    /// Messages without a `project_id` belong to the project that handshaked last, which is filled in.
+    /// Frames that are not messages go to every project.
+    fn route(&self, mut frame: Frame) {
+        if let Frame::Method(method) = &mut frame && let Some((id, _)) = self.projects.last() {
+            method.fill_project_id(id);
+        }
+
+        let routes = self.routes.lock().unwrap();
+        match &frame {
+            Frame::Method(method) => {
+                if let Some(id) = method.project_id() {
+                    if let Some(variables) = routes.variables.get(id) {
+                        variables.apply(method);
+                    }
+                    if let Some(project) = routes.projects.get(id) {
+                        let _ = project.send(frame.clone());
+                    }
+                }
+            }
+            _ => {
+                for project in routes.projects.values() {
+                    let _ = project.send(frame.clone());
+                }
+            }
+        }
+        if let Some(all) = &routes.all {
+            let _ = all.send(frame);
+        }
+    }
+
+    fn announce(&self, event: ConnectionEvent) {
+        let _ = self.events.send(event.clone());
+        self.route(Frame::Connection(event));
+    }
+
+    /// Retry connecting until it succeeds or the attempts run out.
+    async fn reestablish(&mut self, reconnect: &Reconnect) -> Option<Socket> {
+        let mut attempt = 0_u32;
+        loop {
+            attempt += 1;
+            if reconnect.max_attempts.is_some_and(|max| attempt > max) {
+                self.announce(ConnectionEvent::GaveUp);
+                return None;
+            }
+
+            let delay = reconnect.delay(attempt);
+            self.announce(ConnectionEvent::Reconnecting { attempt, delay });
+
+            // Keep accepting messages while waiting so they can be replayed.
+            let sleep = tokio::time::sleep(delay);
+            tokio::pin!(sleep);
+            loop {
+                tokio::select! {
+                    _ = &mut sleep => break,
+                    out = self.outgoing.recv() => match out {
+                        Some(out) => self.enqueue(out),
+                        None => return None,
+                    },
+                }
+            }
+
+            let Ok(mut socket) = self.dialer.dial().await else {
+                continue;
+            };
+            if self.resume(&mut socket).await.is_ok() {
+                self.announce(ConnectionEvent::Connected);
+                return Some(socket);
+            }
+        }
+    }
+
+    /// Re-handshake every project. The queue is replayed by [`Self::pump`].
+    async fn resume(&mut self, socket: &mut Socket) -> Result<(), tungstenite::Error> {
+        // Handshakes queued meanwhile for known projects are repeated here, after the others.
+        let (repeated, queue): (VecDeque<_>, _) = core::mem::take(&mut self.queue)
+            .into_iter()
+            .partition(|out| {
+                matches!(&out.kind, Kind::Handshake(id) if self.projects.iter().any(|(project, _)| project == id))
+            });
+        self.queue = queue;
+        for out in &repeated {
+            if let Kind::Handshake(id) = &out.kind {
+                self.handshaked(id.clone(), out.text.clone());
+            }
+        }
+
+        for (_, handshake) in &self.projects {
+            if let Err(err) = socket.send(Message::text(handshake.as_str())).await {
+                for out in repeated.into_iter().rev() {
+                    self.queue.push_front(out);
+                }
+                return Err(err);
+            }
+        }
+        for out in repeated {
+            self.sent(out);
+        }
+
+        Ok(())
+    }
+
+    /// Remember a handshake to repeat, as the latest one.
+    fn handshaked(&mut self, id: String, text: String) {
+        self.projects.retain(|(project, _)| *project != id);
+        self.projects.push((id, text));
+    }
+
+    fn sent(&mut self, out: Outgoing) {
+        match out.kind {
+            Kind::Handshake(id) => self.handshaked(id, out.text),
+            Kind::Change(method) => {
+                let variables = method
+                    .project_id()
+                    .and_then(|id| self.routes.lock().unwrap().variables.get(id).cloned());
+                if let Some(variables) = variables {
+                    variables.apply(&method);
+                }
+            }
+            Kind::Message => {}
+        }
+        for done in out.done {
+            let _ = done.send(Ok(()));
+        }
     }
 }
 
 pub struct CloudProject {
     id: String,
     cloud: Cloud,
+    variables: Arc<Variables>,
+    incoming: Mutex<broadcast::Receiver<Frame>>,
 }
 
 impl CloudProject {
@@ -96,37 +1104,207 @@ impl CloudProject {
     pub async fn connect(
         username: String,
         project_id: String
-    ) -> Result<Self, Box<dyn core::error::Error>> {
+    ) -> Result<Self, crate::Error> {
         let cloud = Cloud::connect(username).await?;
         Ok(cloud.project(project_id))
     }
 
     /// Handshake with the server.
+    ///
+    /// In reconnecting mode, the handshake is repeated automatically after every reconnect.
     pub async fn handshake(&self) -> Result<(), SendError> {
-        self.cloud.send(
+        let binding = serde_json::to_string(
             &ijson::ijson!({
-            "method": "handshake",
-            "user": self.cloud.user.as_str(),
-            "proejct_id": self.id.as_str(),
-        })
-        ).await
+                "method": "handshake",
+                "user": self.cloud.user.as_str(),
+                "project_id": self.id.as_str(),
+            })
+        );
+        let Ok(serialized) = binding else {
+            return Err(SendError::Serializing(binding.unwrap_err()));
+        };
+
+        self.cloud.dispatch(serialized, Kind::Handshake(self.id.clone())).await
     }
 
     /// Set a cloud variable.
-    pub async fn set(&self, var: &str, value: &str) -> Result<(), SendError> {
-        self.cloud.send(
-            &ijson::ijson!({
-                "method": "set",
-                "name": format!("{}{}", CLOUD, var.trim_start_matches(CLOUD)),
-                "project_id": &self.id,
-                "value": value
-            })
-        ).await
+    ///
+    /// The value is checked against the [limits](CloudBuilder::limits) of the server first.
+    pub async fn set(&self, var: &str, value: &str) -> Result<(), SetError> {
+        self.cloud.limits.check(value)?;
+        self.write(CloudMethod::Set {
+            name: cloud_name(var),
+            user: None,
+            project_id: Some(self.id.clone()),
+            value: value.to_string(),
+        }).await?;
+        Ok(())
+    }
+
+    /// Set a payload too long for one variable across several, in order, splitting it between the codes
+    /// of `codec`, such as [`Encoding::CODEC`](crate::encoding::Encoding::CODEC).
+    ///
+    /// Variables left over are set to the empty string, so [`Self::get_chunked`] reads back the same payload.
+    pub async fn set_chunked(&self, vars: &[&str], payload: &str, codec: Codec) -> Result<(), SetError> {
+        let chunks = self.cloud.limits.chunks_for(vars.len(), payload, codec)?;
+        for (idx, var) in vars.iter().enumerate() {
+            self.set(var, chunks.get(idx).copied().unwrap_or("")).await?;
+        }
+        Ok(())
+    }
+
+    /// Read back a payload written with [`Self::set_chunked`] from the local mirror.
+    pub fn get_chunked(&self, vars: &[&str]) -> Option<String> {
+        vars.iter().map(|var| self.get(var)).collect()
+    }
+
+    /// Create a cloud variable.
+    pub async fn create(&self, var: &str, value: &str) -> Result<(), SetError> {
+        self.cloud.limits.check(value)?;
+        self.write(CloudMethod::Create {
+            name: cloud_name(var),
+            user: None,
+            project_id: Some(self.id.clone()),
+            value: value.to_string(),
+        }).await?;
+        Ok(())
+    }
+
+    /// Delete a cloud variable.
+    pub async fn delete(&self, var: &str) -> Result<(), SendError> {
+        self.write(CloudMethod::Delete {
+            name: cloud_name(var),
+            user: None,
+            project_id: Some(self.id.clone()),
+        }).await
+    }
+
+    /// Rename a cloud variable.
+    pub async fn rename(&self, var: &str, new_name: &str) -> Result<(), SendError> {
+        self.write(CloudMethod::Rename {
+            name: cloud_name(var),
+            new_name: cloud_name(new_name),
+            user: None,
+            project_id: Some(self.id.clone()),
+        }).await
+    }
+
+    /// Send a change. It is applied to the local mirror once written, since the server does not echo it back.
+    async fn write(&self, method: CloudMethod) -> Result<(), SendError> {
+        let binding = serde_json::to_string(&method);
+        let Ok(serialized) = binding else {
+            return Err(SendError::Serializing(binding.unwrap_err()));
+        };
+
+        self.cloud.dispatch(serialized, Kind::Change(method)).await
+    }
+
+    /// Next item of this project.
+    ///
+    /// Each `CloudProject` receives its own copy of the project's items, so several projects (or
+    /// several `CloudProject`s of the same project) can be read concurrently.
+    pub async fn next(&self) -> Option<Result<CloudMethod, NextError>> {
+        recv(&self.incoming).await
+    }
+
+    /// A stream of the events of this project, and of the connection.
+    pub fn stream(&self) -> CloudEvents {
+        CloudEvents::new(self.cloud.routes.lock().unwrap().subscribe_project(&self.id))
+    }
+
+    /// The last known value of a cloud variable.
+    pub fn get(&self, var: &str) -> Option<String> {
+        self.variables.get(&cloud_name(var))
+    }
+
+    /// The last known values of every cloud variable, by name (including the `☁ ` prefix).
+    pub fn snapshot(&self) -> HashMap<String, String> {
+        self.variables.snapshot()
+    }
+
+    /// Watch a cloud variable for changes. The value is `None` while the variable does not exist.
+    pub fn watch(&self, var: &str) -> watch::Receiver<Option<String>> {
+        self.variables.watch(&cloud_name(var))
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase", tag = "method")]
+/// Live values of a project's cloud variables, fed by the handshake dump and later messages.
+#[derive(Default)]
+struct Variables {
+    values: StdMutex<HashMap<String, watch::Sender<Option<String>>>>,
+}
+
+impl Variables {
+    fn apply(&self, method: &CloudMethod) {
+        let mut values = self.values.lock().unwrap();
+        match method {
+            CloudMethod::Set { name, value, .. } | CloudMethod::Create { name, value, .. } => {
+                Self::update(&mut values, name, Some(value.clone()));
+            }
+            CloudMethod::Delete { name, .. } => Self::update(&mut values, name, None),
+            CloudMethod::Rename { name, new_name, .. } => {
+                let value = values.get(name).and_then(|sender| sender.borrow().clone());
+                Self::update(&mut values, name, None);
+                Self::update(&mut values, new_name, value);
+            }
+            CloudMethod::Handshake { .. } | CloudMethod::Unknown(_) => {}
+        }
+    }
+
+    fn update(
+        values: &mut HashMap<String, watch::Sender<Option<String>>>,
+        name: &str,
+        value: Option<String>
+    ) {
+        match values.get(name) {
+            Some(sender) => {
+                sender.send_replace(value);
+            }
+            None if value.is_some() => {
+                values.insert(name.to_string(), watch::Sender::new(value));
+            }
+            None => {}
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<String> {
+        self.values.lock().unwrap().get(name)?.borrow().clone()
+    }
+
+    fn snapshot(&self) -> HashMap<String, String> {
+        self.values
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(name, sender)| Some((name.clone(), sender.borrow().clone()?)))
+            .collect()
+    }
+
+    fn watch(&self, name: &str) -> watch::Receiver<Option<String>> {
+        self.values
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_insert_with(|| watch::Sender::new(None))
+            .subscribe()
+    }
+}
+
+/// Prefix a variable name with `☁ ` unless it already is.
+pub(crate) fn cloud_name(var: &str) -> String {
+    format!("{}{}", CLOUD, var.trim_start_matches(CLOUD))
+}
+
+/// A message of the cloud protocol.
+///
+/// The server leaves out `user` and `project_id` in some messages (such as the variables sent after a
+/// handshake), and values may be sent as numbers; those are converted to strings.
+///
+/// Only a `method` this crate does not know becomes [`Unknown`](Self::Unknown); a known one with missing
+/// or mistyped fields fails to deserialize.
+// `remote = "Self"` turns the derives into inherent functions, wrapped by the trait impls below.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(remote = "Self", rename_all = "lowercase", tag = "method")]
 pub enum CloudMethod {
     Handshake {
         user: String,
@@ -134,8 +1312,303 @@ pub enum CloudMethod {
     },
     Set {
         name: String,
-        user: String,
-        project_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        user: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        project_id: Option<String>,
+        #[serde(deserialize_with = "string_or_number")]
+        value: String,
+    },
+    Create {
+        name: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        user: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        project_id: Option<String>,
+        #[serde(deserialize_with = "string_or_number")]
         value: String,
     },
+    Delete {
+        name: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        user: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        project_id: Option<String>,
+    },
+    Rename {
+        name: String,
+        new_name: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        user: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        project_id: Option<String>,
+    },
+    /// Any other message, including methods this crate does not know yet.
+    #[serde(untagged, skip_deserializing)]
+    Unknown(serde_json::Value),
+}
+
+impl CloudMethod {
+    const METHODS: [&str; 5] = ["handshake", "set", "create", "delete", "rename"];
+}
+
+impl Serialize for CloudMethod {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        CloudMethod::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for CloudMethod {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        let known = value
+            .get("method")
+            .and_then(serde_json::Value::as_str)
+            .is_some_and(|method| Self::METHODS.contains(&method));
+        if !known {
+            return Ok(Self::Unknown(value));
+        }
+        CloudMethod::deserialize(value).map_err(serde::de::Error::custom)
+    }
+}
+
+impl CloudMethod {
+    /// The project this message is about, if the server said so.
+    pub fn project_id(&self) -> Option<&str> {
+        match self {
+            Self::Handshake { project_id, .. } => Some(project_id),
+            | Self::Set { project_id, .. }
+            | Self::Create { project_id, .. }
+            | Self::Delete { project_id, .. }
+            | Self::Rename { project_id, .. } => project_id.as_deref(),
+            Self::Unknown(value) => value.get("project_id")?.as_str(),
+        }
+    }
+}
+
+impl CloudMethod {
+    /// Set the `project_id` of a message from the server that left it out.
+    fn fill_project_id(&mut self, id: &str) {
+        match self {
+            | Self::Set { project_id, .. }
+            | Self::Create { project_id, .. }
+            | Self::Delete { project_id, .. }
+            | Self::Rename { project_id, .. } => {
+                project_id.get_or_insert_with(|| id.to_string());
+            }
+            Self::Handshake { .. } | Self::Unknown(_) => {}
+        }
+    }
+}
+
+fn string_or_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        String(String),
+        Number(serde_json::Number),
+    }
+
+    Ok(match Value::deserialize(deserializer)? {
+        Value::String(s) => s,
+        Value::Number(n) => n.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reconnect_delay() {
+        let reconnect = Reconnect {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            multiplier: 2.0,
+            jitter: 0.0,
+            max_attempts: None,
+        };
+        assert_eq!(reconnect.delay(1), Duration::from_millis(100));
+        assert_eq!(reconnect.delay(3), Duration::from_millis(400));
+        assert_eq!(reconnect.delay(u32::MAX), Duration::from_secs(1));
+
+        let jittered = Reconnect { jitter: 1.0, ..reconnect.clone() };
+        assert!(jittered.delay(3) <= Duration::from_millis(400));
+
+        let nan = Reconnect { multiplier: f64::NAN, jitter: f64::NAN, ..reconnect.clone() };
+        assert_eq!(nan.delay(2), Duration::from_secs(1));
+        let negative = Reconnect { multiplier: -2.0, ..reconnect.clone() };
+        assert_eq!(negative.delay(2), Duration::ZERO);
+
+        // `Duration::MAX` rounds up past the largest `Duration` as seconds in an `f64`.
+        let unbounded = Reconnect { max_delay: Duration::MAX, ..reconnect };
+        assert_eq!(unbounded.delay(u32::MAX), Duration::MAX);
+        assert_eq!(unbounded.delay(2), Duration::from_millis(200));
+    }
+
+    #[cfg(feature = "server")]
+    #[tokio::test]
+    async fn reconnects_and_replays() {
+        use server::{ CloudServer, ServerState };
+
+        const TIMEOUT: Duration = Duration::from_secs(5);
+
+        /// The server ignores sets from clients that have not handshaked, so this also waits for the handshake.
+        async fn stored(state: &ServerState, name: &str, value: &str) {
+            let wait = async {
+                while state.variables("1").get(name).map(String::as_str) != Some(value) {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            };
+            tokio::time::timeout(TIMEOUT, wait).await.unwrap();
+        }
+
+        let server = CloudServer::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = server.endpoint().unwrap();
+        let state = server.state();
+        tokio::spawn(server.run());
+
+        let reconnect = Reconnect {
+            initial_delay: Duration::from_millis(200),
+            jitter: 0.0,
+            ..Reconnect::default()
+        };
+        let cloud = Cloud::builder("user".to_string())
+            .endpoint(endpoint)
+            .reconnect(reconnect)
+            .connect()
+            .await
+            .unwrap();
+        let mut events = cloud.events();
+        let project = cloud.project("1".to_string());
+        project.handshake().await.unwrap();
+        let mut a = project.watch("a");
+        project.set("a", "1").await.unwrap();
+        stored(&state, "☁ a", "1").await;
+
+        assert_eq!(state.kick("1"), 1);
+        let event = tokio::time::timeout(TIMEOUT, events.recv()).await.unwrap().unwrap();
+        assert!(matches!(event, ConnectionEvent::Disconnected { .. }), "{event:?}");
+        state.set("1", "☁ a", "3");
+
+        // Written after the reconnect, behind the repeated handshake.
+        let pending = tokio::spawn(async move { project.set("b", "2").await });
+        loop {
+            match tokio::time::timeout(TIMEOUT, events.recv()).await.unwrap().unwrap() {
+                ConnectionEvent::Reconnecting { attempt, delay } => {
+                    assert_eq!((attempt, delay), (1, Duration::from_millis(200)));
+                }
+                ConnectionEvent::Connected => break,
+                event => panic!("{event:?}"),
+            }
+        }
+        tokio::time::timeout(TIMEOUT, pending).await.unwrap().unwrap().unwrap();
+        stored(&state, "☁ b", "2").await;
+
+        // The handshake dump brings back what changed while disconnected.
+        let seen = a.wait_for(|value| value.as_deref() == Some("3"));
+        tokio::time::timeout(TIMEOUT, seen).await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn messages_without_project_go_to_the_latest_handshake() {
+        const TIMEOUT: Duration = Duration::from_secs(5);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("ws://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+            for _ in 0..3 {
+                socket.next().await.unwrap().unwrap();
+            }
+            socket.send(Message::text(r#"{"method":"set","name":"☁ a","value":"5"}"#)).await.unwrap();
+            socket
+        });
+
+        let cloud = Cloud::builder("user".to_string()).endpoint(endpoint).connect().await.unwrap();
+        let first = cloud.project("1".to_string());
+        let second = cloud.project("2".to_string());
+        first.handshake().await.unwrap();
+        second.handshake().await.unwrap();
+        first.handshake().await.unwrap();
+
+        let mut a = first.watch("a");
+        let seen = a.wait_for(|value| value.as_deref() == Some("5"));
+        tokio::time::timeout(TIMEOUT, seen).await.unwrap().unwrap();
+        assert_eq!(second.get("a"), None);
+        drop(server);
+    }
+
+    #[test]
+    fn chunks_for_only_splits_digits() {
+        let limits = CloudLimits { max_length: 4, numeric_only: true };
+        assert_eq!(limits.chunks_for(3, "1234567890", Codec::Table).unwrap(), ["1234", "5678", "90"]);
+        assert_eq!(limits.chunks_for(1, "", Codec::Table).unwrap(), Vec::<&str>::new());
+
+        // Numbers as a whole, but split into pieces such as "1.23" and "45" or "-123" and "4".
+        for payload in ["-1.25e10", "12e45", "1.2345", "-1234"] {
+            assert!(matches!(limits.chunks_for(3, payload, Codec::Table), Err(SetError::NotNumeric)), "{payload}");
+        }
+        assert!(matches!(limits.chunks_for(2, "1234567890", Codec::Table), Err(SetError::TooLong { length: 10, max: 8 })));
+
+        let text = CloudLimits { max_length: 4, numeric_only: false };
+        assert_eq!(text.chunks_for(2, "ab-cd", Codec::Table).unwrap(), ["ab-c", "d"]);
+    }
+
+    #[test]
+    fn chunks_keep_codes_whole() {
+        use crate::encoding::{ CompactEncoding, ExtendedEncoding };
+
+        let limits = CloudLimits { max_length: 4, numeric_only: true };
+        let payload = CompactEncoding::try_encode("e•HI?!").unwrap();
+        // Splitting every four digits would cut "021" and "022" in two.
+        let chunks = limits.chunks(&payload, CompactEncoding::CODEC).unwrap();
+        assert_eq!(chunks, ["27", "021", "022", "99", "040"]);
+        assert_eq!(chunks.concat(), payload);
+
+        let limits = CloudLimits { max_length: 10, numeric_only: true };
+        let payload = ExtendedEncoding::try_encode("a😀b\u{10FFFF}").unwrap();
+        let chunks = limits.chunks(&payload, ExtendedEncoding::CODEC).unwrap();
+        assert_eq!(chunks, ["1198128512", "12", "9901114111"]);
+        // The long escape alone is too long.
+        let limits = CloudLimits { max_length: 9, numeric_only: true };
+        let err = limits.chunks(&payload, ExtendedEncoding::CODEC).unwrap_err();
+        assert!(matches!(err, SetError::TooLong { length: 22, max: 9 }), "{err:?}");
+    }
+
+    #[test]
+    fn rate_limit_is_clamped() {
+        for per_second in [0.0, -1.0, f64::NAN, f64::NEG_INFINITY] {
+            let builder = Cloud::builder("user".to_string()).rate_limit(RateLimit { per_second, burst: 0 });
+            let limit = builder.rate_limit.unwrap();
+            assert_eq!(limit.per_second, RateLimit::MIN_PER_SECOND);
+
+            let mut bucket = Bucket::new(limit);
+            assert!(!bucket.try_take());
+            assert!(bucket.wait() <= Duration::from_secs(3600));
+        }
+
+        let unlimited = RateLimit { per_second: f64::INFINITY, burst: 1 };
+        let builder = Cloud::builder("user".to_string()).rate_limit(unlimited);
+        assert!(builder.rate_limit.is_none());
+    }
+
+    #[test]
+    fn unknown_methods() {
+        let parse = serde_json::from_str::<CloudMethod>;
+
+        let set = parse(r#"{"method":"set","name":"☁ a","value":12}"#).unwrap();
+        let expected = CloudMethod::Set { name: "☁ a".into(), user: None, project_id: None, value: "12".into() };
+        assert_eq!(set, expected);
+
+        for line in [r#"{"method":"set","name":"☁ a"}"#, r#"{"method":"rename","name":1,"new_name":"b"}"#] {
+            assert!(parse(line).is_err(), "{line}");
+        }
+        for line in [r#"{"method":"ack","name":"☁ a"}"#, r#"{"name":"☁ a"}"#, r#"{"method":5}"#, "[]"] {
+            let method = parse(line).unwrap();
+            assert_eq!(method, CloudMethod::Unknown(serde_json::from_str(line).unwrap()));
+            assert_eq!(serde_json::to_string(&method).unwrap(), line);
+        }
+    }
 }
@@ -0,0 +1,393 @@
+//! A local cloud data server for tests, offline development and self-hosting.
+//!
+//! Speaks the same newline-delimited protocol as `clouddata.scratch.mit.edu`: clients handshake with a
+//! project, receive the current variables, and every `set`, `create`, `delete` and `rename` is applied to the
+//! project and broadcast to the other clients of that project.
+//!
+//! ```no_run
+//! # async fn run() -> std::io::Result<()> {
+//! use scratchback::cloud::server::CloudServer;
+//!
+//! let server = CloudServer::bind("127.0.0.1:0").await?;
+//! println!("listening on {}", server.endpoint()?);
+//! server.run().await
+//! # }
+//! ```
+
+use std::{ collections::{ BTreeMap, HashMap }, io, net::SocketAddr, sync::{ Arc, Mutex } };
+
+use futures_util::{ SinkExt, StreamExt };
+use tokio::{ net::{ TcpListener, TcpStream, ToSocketAddrs }, sync::mpsc };
+use tokio_tungstenite::tungstenite::Message;
+
+use super::CloudMethod;
+
+/// A cloud data server bound to a local address.
+pub struct CloudServer {
+    listener: TcpListener,
+    state: ServerState,
+}
+
+impl CloudServer {
+    /// Bind to an address. Use port `0` to let the OS pick one.
+    pub async fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(addr).await?,
+            state: ServerState::default(),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// The `ws://` URL clients should connect to.
+    pub fn endpoint(&self) -> io::Result<String> {
+        Ok(format!("ws://{}/", self.local_addr()?))
+    }
+
+    /// A handle to the variables of every project, usable while the server runs.
+    pub fn state(&self) -> ServerState {
+        self.state.clone()
+    }
+
+    /// Accept and serve clients until an accept fails.
+    pub async fn run(self) -> io::Result<()> {
+        loop {
+            let (stream, _) = self.listener.accept().await?;
+            tokio::spawn(serve(self.state.clone(), stream));
+        }
+    }
+}
+
+/// Shared state of a [`CloudServer`].
+#[derive(Clone, Default)]
+pub struct ServerState {
+    inner: Arc<Mutex<Projects>>,
+}
+
+#[derive(Default)]
+struct Projects {
+    projects: HashMap<String, Project>,
+    next_client: u64,
+}
+
+#[derive(Default)]
+struct Project {
+    variables: BTreeMap<String, String>,
+    clients: HashMap<u64, mpsc::UnboundedSender<Outbound>>,
+}
+
+/// What the writer of a connection does next.
+enum Outbound {
+    Text(String),
+    Close,
+}
+
+impl Project {
+    fn broadcast(&self, from: Option<u64>, message: String) {
+        for (id, client) in &self.clients {
+            if Some(*id) != from {
+                let _ = client.send(Outbound::Text(message.clone()));
+            }
+        }
+    }
+}
+
+impl ServerState {
+    /// The current variables of a project.
+    pub fn variables(&self, project_id: &str) -> HashMap<String, String> {
+        let inner = self.inner.lock().unwrap();
+        inner.projects
+            .get(project_id)
+            .map(|project| project.variables.clone().into_iter().collect())
+            .unwrap_or_default()
+    }
+
+    /// Set a variable from the server side, broadcasting it to every client of the project.
+    pub fn set(&self, project_id: &str, name: &str, value: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        let project = inner.projects.entry(project_id.to_string()).or_default();
+        project.variables.insert(name.to_string(), value.to_string());
+        project.broadcast(None, set_message(project_id, name, value));
+    }
+
+    /// Close the connection of every client of a project, returning how many there were.
+    ///
+    /// The variables are kept, so clients that reconnect get them back with their handshake.
+    pub fn kick(&self, project_id: &str) -> usize {
+        let inner = self.inner.lock().unwrap();
+        let Some(project) = inner.projects.get(project_id) else {
+            return 0;
+        };
+        for client in project.clients.values() {
+            let _ = client.send(Outbound::Close);
+        }
+        project.clients.len()
+    }
+
+    fn connect(&self, client: mpsc::UnboundedSender<Outbound>) -> Client {
+        let mut inner = self.inner.lock().unwrap();
+        inner.next_client += 1;
+        Client {
+            id: inner.next_client,
+            tx: client,
+            projects: Vec::new(),
+        }
+    }
+
+    fn disconnect(&self, client: &Client) {
+        let mut inner = self.inner.lock().unwrap();
+        for project_id in &client.projects {
+            if let Some(project) = inner.projects.get_mut(project_id) {
+                project.clients.remove(&client.id);
+            }
+        }
+    }
+
+    fn handle(&self, client: &mut Client, line: &str) {
+        let Ok(method) = serde_json::from_str::<CloudMethod>(line) else {
+            return;
+        };
+
+        let mut inner = self.inner.lock().unwrap();
+        match method {
+            CloudMethod::Handshake { project_id, .. } => {
+                let project = inner.projects.entry(project_id.clone()).or_default();
+                project.clients.insert(client.id, client.tx.clone());
+
+                let dump = project.variables
+                    .iter()
+                    .map(|(name, value)| set_message(&project_id, name, value))
+                    .collect::<Vec<_>>();
+                if !dump.is_empty() {
+                    let _ = client.tx.send(Outbound::Text(dump.join("\n")));
+                }
+
+                if !client.projects.contains(&project_id) {
+                    client.projects.push(project_id);
+                }
+            }
+            CloudMethod::Set { name, value, project_id, .. } => {
+                let Some((project_id, project)) = client.project(&mut inner, project_id) else {
+                    return;
+                };
+                project.variables.insert(name.clone(), value.clone());
+                project.broadcast(Some(client.id), set_message(&project_id, &name, &value));
+            }
+            CloudMethod::Create { name, value, project_id, .. } => {
+                let Some((project_id, project)) = client.project(&mut inner, project_id) else {
+                    return;
+                };
+                project.variables.insert(name.clone(), value.clone());
+                project.broadcast(
+                    Some(client.id),
+                    message(
+                        &(CloudMethod::Create { name, value, user: None, project_id: Some(project_id) })
+                    )
+                );
+            }
+            CloudMethod::Delete { name, project_id, .. } => {
+                let Some((project_id, project)) = client.project(&mut inner, project_id) else {
+                    return;
+                };
+                if project.variables.remove(&name).is_some() {
+                    project.broadcast(
+                        Some(client.id),
+                        message(&(CloudMethod::Delete { name, user: None, project_id: Some(project_id) }))
+                    );
+                }
+            }
+            CloudMethod::Rename { name, new_name, project_id, .. } => {
+                let Some((project_id, project)) = client.project(&mut inner, project_id) else {
+                    return;
+                };
+                let Some(value) = project.variables.remove(&name) else {
+                    return;
+                };
+                project.variables.insert(new_name.clone(), value);
+                project.broadcast(
+                    Some(client.id),
+                    message(
+                        &(CloudMethod::Rename { name, new_name, user: None, project_id: Some(project_id) })
+                    )
+                );
+            }
+            CloudMethod::Unknown(_) => {}
+        }
+    }
+}
+
+struct Client {
+    id: u64,
+    tx: mpsc::UnboundedSender<Outbound>,
+    /// Handshaked projects, the latest last.
+    projects: Vec<String>,
+}
+
+impl Client {
+    /// The project a message is meant for, if this client has handshaked with it.
+    ///
+    /// Messages without a `project_id` go to the latest handshake.
+    fn project<'a>(
+        &self,
+        inner: &'a mut Projects,
+        project_id: Option<String>
+    ) -> Option<(String, &'a mut Project)> {
+        let project_id = match project_id {
+            Some(id) if self.projects.contains(&id) => id,
+            Some(_) => {
+                return None;
+            }
+            None => self.projects.last()?.clone(),
+        };
+        let project = inner.projects.get_mut(&project_id)?;
+        Some((project_id, project))
+    }
+}
+
+/// Messages carry the `project_id`, so clients sharing one connection between projects can tell them apart.
+fn set_message(project_id: &str, name: &str, value: &str) -> String {
+    message(
+        &(CloudMethod::Set {
+            name: name.to_string(),
+            user: None,
+            project_id: Some(project_id.to_string()),
+            value: value.to_string(),
+        })
+    )
+}
+
+fn message(method: &CloudMethod) -> String {
+    serde_json::to_string(method).unwrap()
+}
+
+async fn serve(state: ServerState, stream: TcpStream) {
+    let Ok(socket) = tokio_tungstenite::accept_async(stream).await else {
+        return;
+    };
+    let (mut sink, mut stream) = socket.split();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut client = state.connect(tx);
+
+    let writer = async {
+        while let Some(outbound) = rx.recv().await {
+            let Outbound::Text(text) = outbound else {
+                let _ = sink.close().await;
+                break;
+            };
+            if sink.send(Message::text(text)).await.is_err() {
+                break;
+            }
+        }
+    };
+    let reader = async {
+        while let Some(Ok(message)) = stream.next().await {
+            let Ok(text) = message.to_text() else {
+                continue;
+            };
+            for line in text.lines().filter(|line| !line.trim().is_empty()) {
+                state.handle(&mut client, line);
+            }
+        }
+    };
+
+    tokio::select! {
+        _ = writer => {}
+        _ = reader => {}
+    }
+    state.disconnect(&client);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio_tungstenite::{ MaybeTlsStream, WebSocketStream };
+
+    use super::*;
+
+    type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    /// Fail instead of hanging when a message never arrives.
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    async fn start() -> (String, ServerState) {
+        let server = CloudServer::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = server.endpoint().unwrap();
+        let state = server.state();
+        tokio::spawn(server.run());
+        (endpoint, state)
+    }
+
+    async fn handshake(endpoint: &str, project_id: &str) -> Socket {
+        let (mut socket, _) = tokio_tungstenite::connect_async(endpoint).await.unwrap();
+        let handshake = serde_json::json!({ "method": "handshake", "user": "user", "project_id": project_id });
+        socket.send(Message::text(handshake.to_string())).await.unwrap();
+        socket
+    }
+
+    /// The name and value of every set in the next frame.
+    async fn sets(socket: &mut Socket) -> Vec<(String, String)> {
+        let frame = tokio::time::timeout(TIMEOUT, socket.next()).await.unwrap().unwrap().unwrap();
+        frame
+            .to_text()
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let set = serde_json::from_str::<serde_json::Value>(line).unwrap();
+                assert_eq!(set["method"], "set");
+                (set["name"].as_str().unwrap().to_string(), set["value"].as_str().unwrap().to_string())
+            })
+            .collect()
+    }
+
+    fn set(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    #[tokio::test]
+    async fn handshake_dumps_variables() {
+        let (endpoint, state) = start().await;
+        state.set("1", "☁ a", "12");
+        state.set("1", "☁ b", "34");
+        state.set("2", "☁ c", "56");
+
+        let mut socket = handshake(&endpoint, "1").await;
+        assert_eq!(sets(&mut socket).await, [set("☁ a", "12"), set("☁ b", "34")]);
+    }
+
+    #[tokio::test]
+    async fn broadcasts_between_clients() {
+        let (endpoint, state) = start().await;
+        let mut a = handshake(&endpoint, "1").await;
+        let mut b = handshake(&endpoint, "1").await;
+
+        // Arrives either broadcast or in the handshake dump, so both clients have handshaked once it has.
+        state.set("1", "☁ ready", "1");
+        assert_eq!(sets(&mut a).await, [set("☁ ready", "1")]);
+        assert_eq!(sets(&mut b).await, [set("☁ ready", "1")]);
+
+        let line = serde_json::json!({ "method": "set", "name": "☁ x", "value": 5 });
+        a.send(Message::text(line.to_string())).await.unwrap();
+        assert_eq!(sets(&mut b).await, [set("☁ x", "5")]);
+
+        // Nobody receives their own sets back.
+        assert!(tokio::time::timeout(Duration::from_millis(100), a.next()).await.is_err());
+        assert_eq!(state.variables("1").get("☁ x").map(String::as_str), Some("5"));
+    }
+
+    #[tokio::test]
+    async fn kick_closes_connections() {
+        let (endpoint, state) = start().await;
+        let mut socket = handshake(&endpoint, "1").await;
+        state.set("1", "☁ a", "1");
+        assert_eq!(sets(&mut socket).await, [set("☁ a", "1")]);
+
+        assert_eq!(state.kick("1"), 1);
+        let closed = tokio::time::timeout(TIMEOUT, socket.next()).await.unwrap();
+        assert!(matches!(closed, None | Some(Ok(Message::Close(_)))), "{closed:?}");
+        assert_eq!(state.kick("2"), 0);
+        assert_eq!(state.variables("1").len(), 1);
+    }
+}
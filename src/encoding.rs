@@ -2,6 +2,8 @@
 //!
 //! Example:
 //! ```no_run
+//! use scratchback::encoding::ScratchObject;
+//!
 //! #[derive(Debug, ScratchObject)]
 //! struct Person {
 //!     #[id(0)]
@@ -14,22 +16,202 @@
 //!
 //! let decoded = Person::from_sb_encoded(&encoded).unwrap();
 //! println!("{decoded:#?}");
+//!
+//! // Or find out what is wrong with a payload:
+//! if let Err(err) = Person::try_from_sb_encoded("1") {
+//!     println!("{err}");  // Odd number of digits: 1
+//! }
 //! ```
 
+use core::{ fmt, marker::PhantomData, num::NonZero };
+
+pub mod field;
+pub mod integrity;
+pub mod schema;
+pub mod scratch3;
+pub mod serde;
+pub mod stream;
+
+pub use field::{ ItemReader, ItemWriter, ScratchField };
 pub use scratchback_macros::ScratchObject;
+pub use schema::{ Schema, ScratchSchema };
+pub use stream::{ Chars, Item, Items };
 
+/// A value that can be sent through a cloud variable, usually implemented with `#[derive(ScratchObject)]`.
+///
+/// Implementations provide [`try_from_sb_encoded`](Self::try_from_sb_encoded) and
+/// [`try_sb_encode`](Self::try_sb_encode); the other methods are built on them.
 pub trait ScratchObject where Self: Sized {
-    fn from_sb_encoded(numbers: &str) -> Option<Self>;
-    fn sb_encode(self) -> Option<String>;
+    fn try_from_sb_encoded(numbers: &str) -> Result<Self, EncodingError>;
+    fn try_sb_encode(self) -> Result<String, EncodingError>;
+
+    /// The text an encoded value was made from, which is how it is nested in other values. Generated for
+    /// `#[scratch(compact)]` and `#[scratch(table = ...)]`, which encode it with different codes.
+    fn sb_decode_text(numbers: &str) -> Result<String, EncodingError> {
+        ExtendedEncoding::try_decode(numbers)
+    }
+
+    /// The inverse of [`sb_decode_text`](Self::sb_decode_text).
+    fn sb_encode_text(text: &str) -> Result<String, EncodingError> {
+        ExtendedEncoding::try_encode(text)
+    }
+
+    /// Like [`try_sb_encode`](Self::try_sb_encode), with a length prefix and check digits, see [`integrity`].
+    fn try_sb_encode_sealed(self) -> Result<String, EncodingError> {
+        self.try_sb_encode().map(|numbers| integrity::seal(&numbers))
+    }
+
+    /// Decode a value from [`try_sb_encode_sealed`](Self::try_sb_encode_sealed), unless it was cut off or
+    /// changed. Offsets count from the start of `sealed`.
+    fn try_from_sb_sealed(sealed: &str) -> Result<Self, EncodingError> {
+        let payload = integrity::payload(sealed)?;
+        Self::try_from_sb_encoded(&sealed[payload.clone()]).map_err(|err| shift(err, payload.start))
+    }
+
+    fn from_sb_encoded(numbers: &str) -> Option<Self> {
+        Self::try_from_sb_encoded(numbers).ok()
+    }
+
+    fn sb_encode(self) -> Option<String> {
+        self.try_sb_encode().ok()
+    }
+}
+
+/// Why a value could not be encoded or decoded.
+///
+/// Offsets are in bytes: into the digits when decoding, into the text when encoding.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum EncodingError {
+    #[error("Odd number of digits: {length}")] OddLength {
+        length: usize,
+    },
+    #[error("Code {pair:?} at byte {offset} is not in the encoding table")] InvalidCode {
+        offset: usize,
+        pair: String,
+    },
+    #[error("Escape {escape:?} at byte {offset} is not a character")] InvalidEscape {
+        offset: usize,
+        escape: String,
+    },
+    #[error("Character {chr:?} at byte {offset} cannot be encoded")] Unencodable {
+        offset: usize,
+        chr: char,
+    },
+    #[error("Expected {expected} items, got {found}")] ItemCount {
+        expected: usize,
+        found: usize,
+    },
+    #[error("Expected {expected}, got {value:?}")] Parse {
+        expected: &'static str,
+        value: String,
+    },
+    #[error("Ran out of items")] MissingItem,
+    #[error("Unexpected items from byte {offset}")] TrailingItems {
+        offset: usize,
+    },
+    #[error("Expected version {expected}, got {found:?}")] Version {
+        expected: u32,
+        found: String,
+    },
+    #[error("Invalid length prefix")] InvalidPrefix,
+    #[error("No splitter after the variant id")] MissingSplitter,
+    #[error("Unknown variant id {id:?}")] UnknownVariant {
+        id: String,
+    },
+    #[error("Field {id} `{name}` at byte {offset}: {source}")] Field {
+        id: u8,
+        name: &'static str,
+        offset: usize,
+        source: Box<EncodingError>,
+    },
+    #[error("Variant {id:?} at byte {offset}: {source}")] Variant {
+        id: String,
+        offset: usize,
+        source: Box<EncodingError>,
+    },
+    #[error("Element {index} at byte {offset}: {source}")] Element {
+        index: usize,
+        offset: usize,
+        source: Box<EncodingError>,
+    },
+    #[error("Could not write the digits")] Write,
+    #[error("Expected {expected} digits, got {found}")] Length {
+        expected: usize,
+        found: usize,
+    },
+    #[error("Check digits do not match")] Checksum,
+    /// Raised by a `Serialize` or `Deserialize` implementation, see [`serde`](self::serde).
+    #[error("{0}")] Custom(String),
+}
+
+impl EncodingError {
+    /// Attach the field this error happened in. `offset` is where the field starts.
+    pub fn in_field(self, id: u8, name: &'static str, offset: usize) -> Self {
+        Self::Field { id, name, offset, source: Box::new(self) }
+    }
+
+    /// Attach the element of a `Vec`, `Option`, array or tuple this error happened in. `offset` is where the
+    /// element starts, counted from the start of the field.
+    pub fn in_element(self, index: usize, offset: usize) -> Self {
+        Self::Element { index, offset, source: Box::new(self) }
+    }
+
+    /// The offset of the innermost error, counted from the start of the outermost value.
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            | Self::InvalidCode { offset, .. }
+            | Self::InvalidEscape { offset, .. }
+            | Self::Unencodable { offset, .. }
+            | Self::TrailingItems { offset } => Some(*offset),
+            | Self::Field { offset, source, .. }
+            | Self::Variant { offset, source, .. }
+            | Self::Element { offset, source, .. } => {
+                Some(offset + source.offset().unwrap_or(0))
+            }
+            _ => None,
+        }
+    }
 }
 
+/// The characters of an encoding, each encoded as the two digits of its index.
+///
+/// Implemented by the tables [`encoding_table!`](crate::encoding_table) defines. [`TableEncoding`] encodes
+/// with any of them, and `#[scratch(table = ...)]` makes the derive use one.
+pub trait Table {
+    /// The characters, indexed by their code.
+    const CHARS: &'static [char];
+    /// The code of the splitter `•`, which separates items.
+    const SPLITTER_ENCODED: &'static str;
+
+    fn encode(chr: char) -> Option<usize>;
+    fn decode(index: usize) -> Option<char>;
+}
+
+/// Define a [`Table`], to read and write values of projects that number their characters differently.
+///
+/// Indices must count up from `0` in order, and there can be at most 100 of them. One of the characters has
+/// to be the splitter `•`: its code separates items. A project whose list starts at item 1 puts a
+/// placeholder at index `0`, as the [`EncodingTable`] does.
+///
+/// ```
+/// use scratchback::encoding::TableEncoding;
+///
+/// scratchback::encoding_table!(pub LetterTable, [
+///     (0, ' '), (1, 'a'), (2, 'b'), (3, 'c'), (4, '•'),
+/// ]);
+///
+/// assert_eq!(TableEncoding::<LetterTable>::encode("cab a").unwrap(), "0301020001");
+/// ```
+#[macro_export]
 macro_rules! encoding_table {
-    ($name:ident, [$(($idx:expr, $ch:expr)),* $(,)?]) => {
+    ($(#[$attr:meta])* $vis:vis $name:ident, [$(($idx:expr, $ch:expr)),* $(,)?]) => {
         /// An encoding table.
-        pub struct $name;
+        $(#[$attr])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        $vis struct $name;
 
         impl $name {
-            pub const TABLE: [char; count_idents!($( $idx ),*)] = [
+            pub const TABLE: [char; [$( $ch ),*].len()] = [
                 $( $ch ),*
             ];
 
@@ -48,22 +230,57 @@ macro_rules! encoding_table {
                 }
             }
         }
-    };
-}
 
-// count number of elements
-macro_rules! count_idents {
-    ($($x:expr),*) => {
-        <[()]>::len(&[ $(replace_expr!($x ())),* ])
+        impl $crate::encoding::Table for $name {
+            const CHARS: &'static [char] = &Self::TABLE;
+            const SPLITTER_ENCODED: &'static str = {
+                assert!(Self::TABLE.len() <= 100, "A table has at most 100 characters");
+                let mut index = 0;
+                $(
+                    assert!($idx == index, "Indices must count up from 0");
+                    index += 1;
+                )*
+                let _ = index;
+                match Self::encode('•') {
+                    Some(code) => $crate::encoding::TWO_DIGITS[code],
+                    None => panic!("The table needs the splitter '•'"),
+                }
+            };
+
+            fn encode(chr: char) -> Option<usize> {
+                Self::encode(chr)
+            }
+
+            fn decode(index: usize) -> Option<char> {
+                Self::decode(index)
+            }
+        }
     };
 }
 
-macro_rules! replace_expr {
-    ($_t:tt $sub:expr) => {$sub};
-}
+/// `00` to `99`, for [`Table::SPLITTER_ENCODED`].
+#[doc(hidden)]
+pub const TWO_DIGITS: [&str; 100] = {
+    const DIGITS: &[u8; 200] = b"\
+        00010203040506070809101112131415161718192021222324252627282930313233343536373839\
+        40414243444546474849505152535455565758596061626364656667686970717273747576777879\
+        8081828384858687888990919293949596979899";
+    let mut pairs = [""; 100];
+    let mut index = 0;
+    while index < 100 {
+        let (_, rest) = DIGITS.split_at(index * 2);
+        let (pair, _) = rest.split_at(2);
+        pairs[index] = match core::str::from_utf8(pair) {
+            Ok(pair) => pair,
+            Err(_) => unreachable!(),
+        };
+        index += 1;
+    }
+    pairs
+};
 
 #[rustfmt::skip]
-encoding_table!(EncodingTable, [
+encoding_table!(pub EncodingTable, [
     (0, '�'), // Placeholder
 
     (1, '0'), (2, '1'), (3, '2'), (4, '3'), (5, '4'), (6, '5'),
@@ -87,91 +304,464 @@ encoding_table!(EncodingTable, [
 ]);
 
 /// Encoding for `scratchback`.
-pub struct Encoding;
+///
+/// Every character is a two-digit code of the [`EncodingTable`]; others cannot be encoded.
+pub type Encoding = TableEncoding<EncodingTable>;
 
-impl Encoding {
-    pub const SPLITTER: char = '•';
-    pub const SPLITTER_STR: &str = "•";
-    pub const SPLITTER_ENCODED: &str = "97";
+/// [`Encoding`] with the codes of another [`Table`], for projects that already have their own.
+pub struct TableEncoding<T: Table>(PhantomData<T>);
 
-    pub fn encode(input: &str) -> Option<String> {
-        let mut seq = EncodedSequence::new();
+/// Encoding for `scratchback` that can encode any character.
+///
+/// Characters of the [`EncodingTable`] keep their two-digit codes, so text that [`Encoding`] can encode is
+/// encoded the same. Any other character is escaped with its Unicode code point: `98` and 6 digits, or `99`
+/// and 8 digits for code points from 1 000 000 up (the private use planes).
+///
+/// ```text
+/// é   98000233
+/// 😀  98128512
+/// ```
+///
+/// Scratch has no block that turns a code point into a character, so a project reads escapes back by
+/// looking the code point up in two lists it fills itself: `extra codes` with code points and
+/// `extra chars` with the matching characters. `table` holds the 97 characters of the [`EncodingTable`],
+/// starting with `0`.
+///
+/// ```text
+/// define decode (numbers)
+/// set [decoded v] to []
+/// set [i v] to [1]
+/// repeat until <(i) > (length of (numbers))>
+///     set [code v] to (join (letter (i) of (numbers)) (letter ((i) + (1)) of (numbers)))
+///     change [i v] by (2)
+///     if <<(code) = [98]> or <(code) = [99]>> then
+///         if <(code) = [98]> then
+///             set [width v] to [6]
+///         else
+///             set [width v] to [8]
+///         end
+///         set [code v] to []
+///         repeat (width)
+///             set [code v] to (join (code) (letter (i) of (numbers)))
+///             change [i v] by (1)
+///         end
+///         if <[extra codes v] contains ((code) + (0))?> then
+///             set [decoded v] to (join (decoded) (item (item # of ((code) + (0)) in [extra codes v]) of [extra chars v]))
+///         else
+///             set [decoded v] to (join (decoded) [�])
+///         end
+///     else
+///         set [decoded v] to (join (decoded) (item ((code) + (0)) of [table v]))
+///     end
+/// end
+/// ```
+pub struct ExtendedEncoding;
 
-        for chr in input.chars() {
-            let id = EncodingTable::encode(chr)?;
-            seq.add(id);
-        }
+#[rustfmt::skip]
+encoding_table!(pub CompactTable, [
+    (0, ' '), (1, 'e'), (2, 't'), (3, 'a'), (4, 'o'), (5, 'i'), (6, '•'),
 
-        Some(seq.get())
-    }
+    (7, 'n'), (8, 's'), (9, 'r'), (10, 'h'), (11, 'l'), (12, 'd'), (13, 'u'),
+    (14, 'c'), (15, 'm'), (16, 'y'), (17, 'w'), (18, 'g'), (19, 'p'), (20, 'f'),
+    (21, 'b'), (22, 'v'), (23, 'k'), (24, '.'), (25, ','), (26, '?'),
 
-    pub fn encode_items(items: &[&str]) -> Option<String> {
-        Self::encode(&items.join(Self::SPLITTER_STR))
-    }
+    (27, '0'), (28, '1'), (29, '2'), (30, '3'), (31, '4'), (32, '5'), (33, '6'),
+    (34, '7'), (35, '8'), (36, '9'), (37, 'j'), (38, 'q'), (39, 'x'), (40, 'z'),
+    (41, 'A'), (42, 'B'), (43, 'C'), (44, 'D'), (45, 'E'), (46, 'F'), (47, 'G'),
+    (48, 'H'), (49, 'I'), (50, 'J'), (51, 'K'), (52, 'L'), (53, 'M'), (54, 'N'),
+    (55, 'O'), (56, 'P'), (57, 'Q'), (58, 'R'), (59, 'S'), (60, 'T'), (61, 'U'),
+    (62, 'V'), (63, 'W'), (64, 'X'), (65, 'Y'), (66, 'Z'), (67, '!'), (68, '"'),
+    (69, '#'), (70, '$'), (71, '%'), (72, '&'), (73, '\''), (74, '('), (75, ')'),
+    (76, '*'), (77, '+'), (78, '-'), (79, '/'), (80, ':'), (81, ';'), (82, '<'),
+    (83, '='), (84, '>'), (85, '@'), (86, '['), (87, '\\'), (88, ']'), (89, '^'),
+    (90, '_'), (91, '`'), (92, '{'), (93, '|'), (94, '}'), (95, '~'), (96, '\n'),
+]);
 
-    pub fn decode(numbers: &str) -> Option<String> {
-        let mut decoded = String::new();
+/// Encoding for `scratchback` that spends fewer digits on frequent characters, so longer text fits in one
+/// cloud variable.
+///
+/// It encodes the characters of the [`EncodingTable`], in the order of the [`CompactTable`]. The first
+/// digit of a code tells how long it is:
+///
+/// ```text
+/// 1 to 7      space e t a o i •
+/// 80 to 99    n s r h l d u c m y w g p f b v k . , ?
+/// 000 to 069  the other 70 characters
+/// ```
+///
+/// Lowercase text takes about one and a half digits per character instead of two, but digits and capital
+/// letters take three, so values that are mostly numbers are shorter with [`Encoding`]. Choose it per type
+/// with `#[scratch(compact)]`.
+///
+/// `compact` holds the 97 characters of the [`CompactTable`], starting with the space, so the index of a
+/// character is what its code decodes to:
+///
+/// ```text
+/// define decode (numbers)
+/// set [decoded v] to []
+/// set [i v] to [1]
+/// repeat until <(i) > (length of (numbers))>
+///     set [code v] to (letter (i) of (numbers))
+///     change [i v] by (1)
+///     if <(code) > [7]> then
+///         set [code v] to ((join (code) (letter (i) of (numbers))) - (72))
+///         change [i v] by (1)
+///     end
+///     if <(code) = [0]> then
+///         set [code v] to ((join (letter (i) of (numbers)) (letter ((i) + (1)) of (numbers))) + (28))
+///         change [i v] by (2)
+///     end
+///     set [decoded v] to (join (decoded) (item (code) of [compact v]))
+/// end
+/// ```
+pub struct CompactEncoding;
 
-        if numbers.len() % 2 != 0 {
-            return None;
+macro_rules! encoding_api {
+    (
+        impl[$($generics:tt)*] $name:ty,
+        codec: $codec:expr,
+        table: $table:ty,
+        splitter: $splitter:expr
+    ) => {
+        impl<$($generics)*> $name {
+            /// How this encoding turns characters into digits.
+            pub const CODEC: Codec = $codec;
+            pub const SPLITTER: char = '•';
+            pub const SPLITTER_STR: &str = "•";
+            pub const SPLITTER_ENCODED: &str = $splitter;
+
+            pub fn encode(input: &str) -> Option<String> {
+                Self::try_encode(input).ok()
+            }
+
+            pub fn try_encode(input: &str) -> Result<String, EncodingError> {
+                let mut encoded = String::with_capacity(input.len() * 2);
+                encode_to::<$table, _>(input, $codec, &mut encoded)?;
+                Ok(encoded)
+            }
+
+            /// Append the digits of `input` to `out`, which can be reused between values. On error, `out` is
+            /// left as it was.
+            pub fn try_encode_into(input: &str, out: &mut String) -> Result<(), EncodingError> {
+                let len = out.len();
+                encode_to::<$table, _>(input, $codec, out).inspect_err(|_| out.truncate(len))
+            }
+
+            /// Write the digits of `input` to `out` as they are encoded. On error, the digits before the bad
+            /// character have been written.
+            pub fn try_encode_fmt<W: fmt::Write + ?Sized>(input: &str, out: &mut W) -> Result<(), EncodingError> {
+                encode_to::<$table, _>(input, $codec, out)
+            }
+
+            /// Like [`try_encode_fmt`](Self::try_encode_fmt), for sockets and files. An `EncodingError` is
+            /// returned as [`InvalidInput`](std::io::ErrorKind::InvalidInput).
+            pub fn try_encode_io<W: std::io::Write + ?Sized>(input: &str, out: &mut W) -> std::io::Result<()> {
+                stream::encode_io::<$table, _>(input, $codec, out)
+            }
+
+            /// Encode one item of a list, which must not contain the splitter.
+            pub fn try_encode_item(item: &str) -> Result<String, EncodingError> {
+                if let Some(offset) = item.find(Self::SPLITTER) {
+                    return Err(EncodingError::Unencodable { offset, chr: Self::SPLITTER });
+                }
+                Self::try_encode(item)
+            }
+
+            pub fn encode_items(items: &[&str]) -> Option<String> {
+                Self::try_encode_items(items).ok()
+            }
+
+            pub fn try_encode_items(items: &[&str]) -> Result<String, EncodingError> {
+                let mut encoded = String::new();
+                let mut offset = 0;
+                for item in items {
+                    if offset > 0 {
+                        encoded.push_str(Self::SPLITTER_ENCODED);
+                    }
+                    if let Some(splitter) = item.find(Self::SPLITTER) {
+                        return Err(EncodingError::Unencodable { offset: offset + splitter, chr: Self::SPLITTER });
+                    }
+                    encode_to::<$table, _>(item, $codec, &mut encoded).map_err(|err| shift(err, offset))?;
+                    offset += item.len() + Self::SPLITTER.len_utf8();
+                }
+
+                Ok(encoded)
+            }
+
+            pub fn decode(numbers: &str) -> Option<String> {
+                Self::try_decode(numbers).ok()
+            }
+
+            pub fn try_decode(numbers: &str) -> Result<String, EncodingError> {
+                Self::chars(numbers).collect()
+            }
+
+            /// The characters of `numbers`, decoded one code at a time.
+            pub fn chars(numbers: &str) -> Chars<'_, $table> {
+                Chars::<$table>::new(numbers, $codec)
+            }
+
+            /// The items of `numbers`, split without decoding them. They are those of
+            /// [`try_decode_items`](Self::try_decode_items), and an empty one after a trailing splitter.
+            pub fn items(numbers: &str) -> Items<'_, $table> {
+                Items::<$table>::new(numbers, $codec, Self::SPLITTER_ENCODED)
+            }
+
+            pub fn decode_items(numbers: &str) -> Option<Vec<String>> {
+                Self::try_decode_items(numbers).ok()
+            }
+
+            /// A trailing empty item is left out, so both `a` and `a•` are `["a"]`.
+            pub fn try_decode_items(numbers: &str) -> Result<Vec<String>, EncodingError> {
+                let mut decoded = Vec::new();
+                let mut s = String::new();
+
+                for chr in Self::chars(numbers) {
+                    match chr? {
+                        Self::SPLITTER => decoded.push(core::mem::take(&mut s)),
+                        chr => s.push(chr),
+                    }
+                }
+                if !s.is_empty() {
+                    decoded.push(s);
+                }
+
+                Ok(decoded)
+            }
+
+            /// Where item `index` of `items` starts in the digits they were decoded from.
+            pub fn item_offset(items: &[String], index: usize) -> usize {
+                items[..index]
+                    .iter()
+                    .map(|item| item.chars().map(|chr| width(chr, $codec)).sum::<usize>() + Self::SPLITTER_ENCODED.len())
+                    .sum()
+            }
+
+            /// The offset of the first splitter, never in the middle of a code.
+            pub fn find_splitter(numbers: &str) -> Option<usize> {
+                find_code(numbers, 0, Self::SPLITTER_ENCODED, $codec)
+            }
         }
+    };
+}
+
+encoding_api!(
+    impl[T: Table] TableEncoding<T>,
+    codec: Codec::Table,
+    table: T,
+    splitter: T::SPLITTER_ENCODED
+);
+encoding_api!(
+    impl[] ExtendedEncoding,
+    codec: Codec::Extended,
+    table: EncodingTable,
+    splitter: "97"
+);
+encoding_api!(
+    impl[] CompactEncoding,
+    codec: Codec::Compact,
+    table: CompactTable,
+    splitter: "7"
+);
+
+/// How an encoding turns characters into digits, see [`Encoding::CODEC`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    /// [`Encoding`]
+    Table,
+    /// [`ExtendedEncoding`]
+    Extended,
+    /// [`CompactEncoding`]
+    Compact,
+}
 
-        for idx in (0..numbers.len()).step_by(2) {
-            let num = atoi::atoi::<usize>(&numbers[idx..=idx + 1].as_bytes())?;
-            decoded.push(EncodingTable::decode(num)?);
+impl Codec {
+    /// How many digits the code at the start of `numbers` takes, at most `numbers.len()`.
+    pub fn code_width(self, numbers: &str) -> usize {
+        if numbers.is_empty() {
+            return 0;
         }
+        code_width(numbers.as_bytes(), self).min(numbers.len())
+    }
+}
 
-        Some(decoded)
+const ESCAPE: &str = "98";
+const ESCAPE_LONG: &str = "99";
+
+/// How many digits follow an escape code, if `digits` start with one.
+fn escape_width(digits: &[u8], codec: Codec) -> Option<usize> {
+    match digits {
+        [b'9', b'8', ..] if codec == Codec::Extended => Some(6),
+        [b'9', b'9', ..] if codec == Codec::Extended => Some(8),
+        _ => None,
     }
+}
 
-    pub fn decode_items(numbers: &str) -> Option<Vec<String>> {
-        let mut decoded = Vec::new();
-        let mut s = String::new();
+/// How many digits the code at the start of `digits` takes, without checking it.
+fn code_width(digits: &[u8], codec: Codec) -> usize {
+    match codec {
+        Codec::Compact => match digits[0] {
+            b'0' => 3,
+            b'8' | b'9' => 2,
+            _ => 1,
+        },
+        _ => escape_width(digits, codec).unwrap_or(0) + 2,
+    }
+}
 
-        if numbers.len() % 2 != 0 {
-            return None;
+/// How many digits a character is encoded as.
+fn width(chr: char, codec: Codec) -> usize {
+    if codec == Codec::Compact {
+        return match CompactTable::encode(chr) {
+            Some(0..7) => 1,
+            Some(7..27) => 2,
+            _ => 3,
+        };
+    }
+    match EncodingTable::encode(chr) {
+        None if codec == Codec::Extended && (chr as u32) < 1_000_000 => 8,
+        None if codec == Codec::Extended => 10,
+        _ => 2,
+    }
+}
+
+/// The offset of the first `code` from `from` on, never in the middle of another code.
+fn find_code(numbers: &str, from: usize, code: &str, codec: Codec) -> Option<usize> {
+    let digits = numbers.as_bytes();
+    if codec == Codec::Table {
+        let pairs = digits[from..].chunks_exact(2);
+        return pairs.into_iter().position(|pair| pair == code.as_bytes()).map(|pair| from + pair * 2);
+    }
+
+    let mut idx = from;
+    while idx < digits.len() {
+        if digits[idx..].starts_with(code.as_bytes()) {
+            return Some(idx);
         }
+        idx += code_width(&digits[idx..], codec);
+    }
+    None
+}
 
-        for idx in (0..numbers.len()).step_by(2) {
-            let num = atoi::atoi::<usize>(&numbers[idx..=idx + 1].as_bytes())?;
-            let chr = EncodingTable::decode(num)?;
-            if chr == Self::SPLITTER {
-                decoded.push(s.drain(..).collect());
-            } else {
-                s.push(chr);
+/// Write the codes of table `T`, which is the [`CompactTable`] for [`Codec::Compact`].
+fn encode_to<T: Table, W: fmt::Write + ?Sized>(input: &str, codec: Codec, out: &mut W) -> Result<(), EncodingError> {
+    for (offset, chr) in input.char_indices() {
+        let written = match T::encode(chr) {
+            Some(index) if codec == Codec::Compact => write_compact(out, index),
+            Some(index) => write_pair(out, index),
+            None if codec == Codec::Extended => write_escape(out, chr),
+            None => {
+                return Err(EncodingError::Unencodable { offset, chr });
             }
-        }
-        if !s.is_empty() {
-            decoded.push(s);
-        }
+        };
+        written.map_err(|_| EncodingError::Write)?;
+    }
+
+    Ok(())
+}
 
-        Some(decoded)
+/// Two digits of `n`, which is below 100.
+fn write_pair<W: fmt::Write + ?Sized>(out: &mut W, n: usize) -> fmt::Result {
+    out.write_char(char::from(b'0' + (n / 10) as u8))?;
+    out.write_char(char::from(b'0' + (n % 10) as u8))
+}
+
+/// The code of index `index` of the [`CompactTable`], see [`CompactEncoding`].
+fn write_compact<W: fmt::Write + ?Sized>(out: &mut W, index: usize) -> fmt::Result {
+    match index {
+        0..7 => out.write_char(char::from(b'1' + index as u8)),
+        7..27 => write_pair(out, index + 73),
+        _ => {
+            out.write_char('0')?;
+            write_pair(out, index - 27)
+        }
     }
 }
 
-pub struct EncodedSequence {
-    seq: Vec<String>,
+/// A character outside the table, see [`ExtendedEncoding`].
+fn write_escape<W: fmt::Write + ?Sized>(out: &mut W, chr: char) -> fmt::Result {
+    let code = chr as u32;
+    if code < 1_000_000 {
+        write!(out, "{ESCAPE}{code:06}")
+    } else {
+        write!(out, "{ESCAPE_LONG}{code:08}")
+    }
 }
 
-impl EncodedSequence {
-    /// Creates a new encoded sequence.
-    fn new() -> Self {
-        Self { seq: Vec::new() }
+/// The character of the code at `offset` and how many digits it took. `end` is where the digits of the
+/// value or item end.
+fn decode_char<T: Table>(
+    numbers: &str,
+    offset: usize,
+    end: usize,
+    codec: Codec
+) -> Result<(char, usize), EncodingError> {
+    let digits = &numbers.as_bytes()[..end];
+    if codec == Codec::Compact {
+        return decode_compact_at(digits, offset);
     }
 
-    /// Adds a new item.
-    fn add(&mut self, item: usize) {
-        let mut bf = itoa::Buffer::new();
-        let num = bf
-            .format(item)
-            .get(..)
-            .unwrap();
+    let Some(width) = escape_width(&digits[offset..], codec) else {
+        return Ok((decode_at::<T>(digits, offset)?, 2));
+    };
 
-        self.seq.push(format!("{:0>2}", num))
+    let end = (offset + 2 + width).min(digits.len());
+    let escape = &digits[offset + 2..end];
+    let chr = (escape.len() == width && escape.iter().all(u8::is_ascii_digit))
+        .then(|| atoi::atoi::<u32>(escape))
+        .flatten()
+        .and_then(char::from_u32);
+    let Some(chr) = chr else {
+        return Err(EncodingError::InvalidEscape {
+            offset,
+            escape: String::from_utf8_lossy(&digits[offset..end]).into_owned(),
+        });
+    };
+    Ok((chr, end - offset))
+}
+
+/// The character of the [`CompactEncoding`] code at `offset`, and how many digits it took.
+fn decode_compact_at(digits: &[u8], offset: usize) -> Result<(char, usize), EncodingError> {
+    let end = (offset + code_width(&digits[offset..], Codec::Compact)).min(digits.len());
+    let code = &digits[offset..end];
+    // A code cut off at the end is shorter than its first digit says, and would map to another range.
+    let index = (code.len() == code_width(code, Codec::Compact) && code.iter().all(u8::is_ascii_digit))
+        .then(|| atoi::atoi::<usize>(code))
+        .flatten()
+        .and_then(|value| match code.len() {
+            1 => value.checked_sub(1),
+            2 => value.checked_sub(73),
+            _ => value.checked_add(27),
+        });
+    match index.and_then(CompactTable::decode) {
+        Some(chr) => Ok((chr, code.len())),
+        None => Err(EncodingError::InvalidCode { offset, pair: String::from_utf8_lossy(code).into_owned() }),
     }
+}
 
-    fn get(&self) -> String {
-        self.seq.join("")
+fn decode_at<T: Table>(digits: &[u8], offset: usize) -> Result<char, EncodingError> {
+    let pair = &digits[offset..(offset + 2).min(digits.len())];
+    let chr = match pair {
+        [tens @ b'0'..=b'9', ones @ b'0'..=b'9'] => T::decode(((tens - b'0') * 10 + ones - b'0') as usize),
+        _ => None,
+    };
+    chr.ok_or_else(|| EncodingError::InvalidCode {
+        offset,
+        pair: String::from_utf8_lossy(pair).into_owned(),
+    })
+}
+
+/// Move an error by `by` bytes, for items and nested values encoded separately.
+fn shift(err: EncodingError, by: usize) -> EncodingError {
+    match err {
+        EncodingError::InvalidCode { offset, pair } => EncodingError::InvalidCode { offset: offset + by, pair },
+        EncodingError::InvalidEscape { offset, escape } => EncodingError::InvalidEscape { offset: offset + by, escape },
+        EncodingError::Unencodable { offset, chr } => EncodingError::Unencodable { offset: offset + by, chr },
+        EncodingError::TrailingItems { offset } => EncodingError::TrailingItems { offset: offset + by },
+        EncodingError::Field { id, name, offset, source } => EncodingError::Field { id, name, offset: offset + by, source },
+        EncodingError::Variant { id, offset, source } => EncodingError::Variant { id, offset: offset + by, source },
+        EncodingError::Element { index, offset, source } => EncodingError::Element { index, offset: offset + by, source },
+        err => err,
     }
 }
 
@@ -213,6 +803,54 @@ impl_atoi_sbstringto!(i8);
 impl_atoi_sbstringto!(i16);
 impl_atoi_sbstringto!(i32);
 impl_atoi_sbstringto!(i64);
+impl_atoi_sbstringto!(i128);
+impl_atoi_sbstringto!(u128);
+impl_atoi_sbstringto!(isize);
+impl_atoi_sbstringto!(usize);
+
+impl SbStringTo<char> for String {
+    fn sb_string_to(&self) -> Option<char> {
+        let mut chars = self.chars();
+        chars.next().filter(|_| chars.next().is_none())
+    }
+}
+
+/// Accepts everything Scratch renders, including `1e+21`, `Infinity` and `NaN`.
+macro_rules! impl_float_sbstringto {
+    ($typ:ty) => {
+        impl SbStringTo<$typ> for String {
+            fn sb_string_to(&self) -> Option<$typ> {
+                self.parse().ok()
+            }
+        }
+    };
+}
+
+impl_float_sbstringto!(f32);
+impl_float_sbstringto!(f64);
+
+macro_rules! impl_nonzero_sbstringto {
+    ($typ:ty) => {
+        impl SbStringTo<NonZero<$typ>> for String {
+            fn sb_string_to(&self) -> Option<NonZero<$typ>> {
+                NonZero::new(SbStringTo::<$typ>::sb_string_to(self)?)
+            }
+        }
+    };
+}
+
+impl_nonzero_sbstringto!(u8);
+impl_nonzero_sbstringto!(u16);
+impl_nonzero_sbstringto!(u32);
+impl_nonzero_sbstringto!(u64);
+impl_nonzero_sbstringto!(u128);
+impl_nonzero_sbstringto!(usize);
+impl_nonzero_sbstringto!(i8);
+impl_nonzero_sbstringto!(i16);
+impl_nonzero_sbstringto!(i32);
+impl_nonzero_sbstringto!(i64);
+impl_nonzero_sbstringto!(i128);
+impl_nonzero_sbstringto!(isize);
 
 pub trait SbToString {
     fn sb_to_string(&self) -> String;
@@ -252,3 +890,287 @@ impl_atoi_sbtostring!(i8);
 impl_atoi_sbtostring!(i16);
 impl_atoi_sbtostring!(i32);
 impl_atoi_sbtostring!(i64);
+impl_atoi_sbtostring!(i128);
+impl_atoi_sbtostring!(u128);
+impl_atoi_sbtostring!(isize);
+impl_atoi_sbtostring!(usize);
+
+impl SbToString for char {
+    fn sb_to_string(&self) -> String {
+        self.to_string()
+    }
+}
+
+/// Rendered like Scratch (JavaScript's `Number.prototype.toString`) does: `1e+21`, `1.5e-7`, `Infinity`,
+/// `NaN`, and `0` for `-0`, which Scratch does not tell apart from `0`.
+macro_rules! impl_float_sbtostring {
+    ($typ:ty) => {
+        impl SbToString for $typ {
+            fn sb_to_string(&self) -> String {
+                if self.is_nan() {
+                    "NaN".to_string()
+                } else if self.is_infinite() {
+                    match self.is_sign_negative() {
+                        true => "-Infinity".to_string(),
+                        false => "Infinity".to_string(),
+                    }
+                } else if *self == 0.0 {
+                    "0".to_string()
+                } else {
+                    // `{:e}` gives the shortest digits that read back as the same value.
+                    scratch_number(&format!("{self:e}"))
+                }
+            }
+        }
+    };
+}
+
+impl_float_sbtostring!(f32);
+impl_float_sbtostring!(f64);
+
+/// Lay out a number written as `{:e}` the way JavaScript does: plain up to 21 integer digits and down to
+/// 6 leading zeros after the point, with an exponent otherwise.
+fn scratch_number(scientific: &str) -> String {
+    let (sign, scientific) = match scientific.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", scientific),
+    };
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let digits = mantissa.replace('.', "");
+    let k = digits.len() as i32;
+    // Where the point goes, counted in digits from the left.
+    let n = exponent.parse::<i32>().unwrap() + 1;
+
+    if k <= n && n <= 21 {
+        format!("{sign}{digits}{}", "0".repeat((n - k) as usize))
+    } else if 0 < n && n <= 21 {
+        let (int, frac) = digits.split_at(n as usize);
+        format!("{sign}{int}.{frac}")
+    } else if -6 < n && n <= 0 {
+        format!("{sign}0.{}{digits}", "0".repeat(-n as usize))
+    } else {
+        let (first, rest) = digits.split_at(1);
+        let point = if rest.is_empty() { "" } else { "." };
+        let exp_sign = if n > 0 { "+" } else { "-" };
+        format!("{sign}{first}{point}{rest}e{exp_sign}{}", (n - 1).abs())
+    }
+}
+
+macro_rules! impl_nonzero_sbtostring {
+    ($typ:ty) => {
+        impl SbToString for NonZero<$typ> {
+            fn sb_to_string(&self) -> String {
+                self.get().sb_to_string()
+            }
+        }
+    };
+}
+
+impl_nonzero_sbtostring!(u8);
+impl_nonzero_sbtostring!(u16);
+impl_nonzero_sbtostring!(u32);
+impl_nonzero_sbtostring!(u64);
+impl_nonzero_sbtostring!(u128);
+impl_nonzero_sbtostring!(usize);
+impl_nonzero_sbtostring!(i8);
+impl_nonzero_sbtostring!(i16);
+impl_nonzero_sbtostring!(i32);
+impl_nonzero_sbtostring!(i64);
+impl_nonzero_sbtostring!(i128);
+impl_nonzero_sbtostring!(isize);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    encoding_table!(LetterTable, [(0, ' '), (1, 'a'), (2, 'b'), (3, 'c'), (4, '•'), (5, '0'), (6, '1'), (7, '2')]);
+
+    #[derive(Debug, Clone, PartialEq, ScratchObject)]
+    #[scratch(table = LetterTable)]
+    enum Letters {
+        #[id(0)]
+        Unit,
+        #[id(1)]
+        Pair(String, u8),
+        #[id(2)]
+        Named {
+            #[id(0)]
+            name: String,
+        },
+    }
+
+    #[derive(Debug, Clone, PartialEq, ScratchObject)]
+    #[scratch(compact)]
+    enum Command {
+        #[id(0)]
+        Stop,
+        #[id(12)]
+        Say(String, u8),
+        #[id(3)]
+        Move {
+            #[id(0)]
+            x: i32,
+        },
+    }
+
+    #[test]
+    fn compact_enum() {
+        assert_eq!(Command::Stop.try_sb_encode().unwrap(), "0007");
+        assert_eq!(Command::Say("hi".to_string(), 5).try_sb_encode().unwrap(), "00100278367005");
+        for value in [Command::Stop, Command::Say("a b".to_string(), 200), Command::Move { x: -4 }] {
+            let numbers = value.clone().try_sb_encode().unwrap();
+            assert_eq!(Command::try_from_sb_encoded(&numbers).unwrap(), value);
+        }
+        const { assert!(Command::SCHEMA.compact) };
+
+        // The compact splitter is one digit, so the payload starts right after `0037`.
+        let numbers = CompactEncoding::try_encode("3•a").unwrap();
+        let err = Command::try_from_sb_encoded(&numbers).unwrap_err();
+        assert!(matches!(err, EncodingError::Variant { offset: 4, .. }), "{err:?}");
+    }
+
+    #[test]
+    fn enum_with_table() {
+        assert_eq!(Letters::Unit.try_sb_encode().unwrap(), "0504");
+        assert_eq!(Letters::Pair("ab".to_string(), 12).try_sb_encode().unwrap(), "06040102040607");
+        for value in [Letters::Unit, Letters::Pair("cab".to_string(), 0), Letters::Named { name: "a b".to_string() }] {
+            let numbers = value.clone().try_sb_encode().unwrap();
+            assert_eq!(Letters::try_from_sb_encoded(&numbers).unwrap(), value);
+        }
+        assert_eq!(
+            Letters::Named { name: "d".to_string() }.try_sb_encode(),
+            Err(EncodingError::Variant {
+                id: "2".to_string(),
+                offset: 4,
+                source: Box::new(EncodingError::Unencodable { offset: 0, chr: 'd' }.in_field(0, "name", 0)),
+            })
+        );
+        assert_eq!(Letters::SCHEMA.table, Some(&LetterTable::TABLE[..]));
+    }
+
+    #[derive(Debug, PartialEq, ScratchObject)]
+    struct Unversioned {
+        #[id(0)]
+        name: String,
+    }
+
+    #[derive(Debug, PartialEq, ScratchObject)]
+    #[scratch(version = 2)]
+    struct Version2 {
+        #[id(0)]
+        name: String,
+    }
+
+    #[derive(Debug, PartialEq, ScratchObject)]
+    #[scratch(version = 3)]
+    struct Version3 {
+        #[id(0)]
+        name: String,
+        #[id(1, default)]
+        level: u8,
+    }
+
+    #[derive(Debug, PartialEq, ScratchObject)]
+    #[scratch(extended)]
+    struct Greeting {
+        #[id(0)]
+        text: String,
+    }
+
+    #[derive(Debug, PartialEq, ScratchObject)]
+    #[scratch(version = 4)]
+    enum Message {
+        #[id(0)]
+        Ping,
+        #[id(1)]
+        Greet(Greeting),
+    }
+
+    #[test]
+    fn trailing_splitter() {
+        let items = |numbers: &str| Encoding::try_decode_items(numbers).unwrap();
+        let split = Encoding::SPLITTER_ENCODED;
+        let a = Encoding::encode("a").unwrap();
+
+        assert_eq!(items(""), Vec::<String>::new());
+        assert_eq!(items(&a), ["a"]);
+        assert_eq!(items(&format!("{a}{split}")), ["a"]);
+        assert_eq!(items(&format!("{a}{split}{split}")), ["a", ""]);
+        assert_eq!(items(split), [""]);
+    }
+
+    #[test]
+    fn version_mismatch() {
+        let unversioned = Unversioned { name: "x".to_string() }.try_sb_encode().unwrap();
+        let version2 = Version2 { name: "x".to_string() }.try_sb_encode().unwrap();
+        assert_eq!(Encoding::decode(&version2).unwrap(), "2•x");
+
+        assert_eq!(Version2::try_from_sb_encoded(&version2).unwrap(), Version2 { name: "x".to_string() });
+        assert_eq!(
+            Version2::try_from_sb_encoded(&unversioned),
+            Err(EncodingError::Version { expected: 2, found: "x".to_string() })
+        );
+        assert_eq!(
+            Version3::try_from_sb_encoded(&version2),
+            Err(EncodingError::Version { expected: 3, found: "2".to_string() })
+        );
+        assert_eq!(Version3::SCHEMA.read_version(&version2), Ok(Some(2)));
+        assert_eq!(Unversioned::SCHEMA.read_version(&version2), Ok(None));
+    }
+
+    #[test]
+    fn enum_version() {
+        let ping = Message::Ping.try_sb_encode().unwrap();
+        assert_eq!(Encoding::decode(&ping).unwrap(), "4•0•");
+        assert_eq!(Message::try_from_sb_encoded(&ping).unwrap(), Message::Ping);
+
+        // The payload has escapes `Encoding` cannot decode, which `read_version` does not get to.
+        let greet = Message::Greet(Greeting { text: "héllo".to_string() }).try_sb_encode().unwrap();
+        assert_eq!(Message::SCHEMA.read_version(&greet), Ok(Some(4)));
+        assert_eq!(
+            Message::try_from_sb_encoded(&greet).unwrap(),
+            Message::Greet(Greeting { text: "héllo".to_string() })
+        );
+
+        // An unversioned `Ping`, whose id is read as the version.
+        let old = Encoding::encode("0•").unwrap();
+        assert_eq!(
+            Message::try_from_sb_encoded(&old),
+            Err(EncodingError::Version { expected: 4, found: "0".to_string() })
+        );
+        let other = Encoding::encode("5•0•").unwrap();
+        assert_eq!(
+            Message::try_from_sb_encoded(&other),
+            Err(EncodingError::Version { expected: 4, found: "5".to_string() })
+        );
+    }
+
+    #[test]
+    fn compact_round_trip() {
+        let text = "the quick brown fox, 42 JUMPS?\n•";
+        let numbers = CompactEncoding::try_encode(text).unwrap();
+        assert_eq!(CompactEncoding::try_decode(&numbers).unwrap(), text);
+    }
+
+    #[test]
+    fn compact_rejects_truncated_codes() {
+        for numbers in ["0", "05", "8", "9", "10", "120"] {
+            let err = CompactEncoding::try_decode(numbers).unwrap_err();
+            assert!(matches!(err, EncodingError::InvalidCode { .. }), "{numbers}: {err:?}");
+        }
+        assert_eq!(
+            CompactEncoding::try_decode("105"),
+            Err(EncodingError::InvalidCode { offset: 1, pair: "05".to_string() })
+        );
+    }
+
+    #[test]
+    fn compact_rejects_garbage() {
+        for numbers in ["a", "1a", "0a1", "8x", "099", "-1", "1 "] {
+            assert!(CompactEncoding::try_decode(numbers).is_err(), "{numbers}");
+            for item in CompactEncoding::items(numbers).flatten() {
+                assert!(item.decode_into(&mut String::new()).is_err(), "{numbers}");
+            }
+        }
+    }
+}
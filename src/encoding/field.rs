@@ -0,0 +1,578 @@
+//! The types a `#[derive(ScratchObject)]` field can have, and how their items are laid out.
+//!
+//! Strings, numbers and booleans are a plain item. Nested `ScratchObject`s, `Vec`, `Option`, arrays and
+//! tuples are one *prefixed* item instead: the length of their text in characters, a `:`, and their own
+//! items joined with the splitter. The length tells the reader where the item ends, so splitters inside it
+//! cannot cut the outer value apart.
+//!
+//! ```text
+//! Team { name: "Red", players: vec!["Al", "Bo"] }
+//! Red•7:2•Al•Bo
+//! ```
+//!
+//! A `Vec` starts with its length and an `Option` with `0` or `1`, followed by the elements. Arrays and tuples
+//! are just their elements, and a nested `ScratchObject` is the text of its own encoding.
+
+use core::num::NonZero;
+
+use super::{
+    shift,
+    width,
+    Codec,
+    EncodingError,
+    EncodingTable,
+    SbStringTo,
+    SbToString,
+    ScratchObject,
+    Table,
+};
+
+const SPLITTER: char = '•';
+/// Ends the length of a prefixed item.
+const PREFIX_END: char = ':';
+
+/// A value that can be a field of a `#[derive(ScratchObject)]` struct.
+pub trait ScratchField: Sized {
+    fn write_to(self, items: &mut ItemWriter) -> Result<(), EncodingError>;
+    fn read_from(items: &mut ItemReader<'_>) -> Result<Self, EncodingError>;
+}
+
+/// Joins items into the text of a value, which is then encoded as a whole.
+///
+/// Offsets are in bytes of the text; those of elements count from the start of the text of their nested
+/// value, after its length prefix.
+#[derive(Debug)]
+pub struct ItemWriter {
+    text: String,
+    items: usize,
+    /// `encode` of the table every character must be in, `None` to allow any.
+    table: Option<fn(char) -> Option<usize>>,
+}
+
+impl ItemWriter {
+    /// `extended` allows characters outside the [`EncodingTable`].
+    pub fn new(extended: bool) -> Self {
+        let table = (!extended).then_some(<EncodingTable as Table>::encode as fn(char) -> Option<usize>);
+        Self { text: String::new(), items: 0, table }
+    }
+
+    /// Only allow the characters of table `T`, for [`TableEncoding`](super::TableEncoding).
+    pub fn with_table<T: Table>() -> Self {
+        Self { text: String::new(), items: 0, table: Some(T::encode) }
+    }
+
+    /// Where the next item will start.
+    pub fn offset(&self) -> usize {
+        match self.items {
+            0 => 0,
+            _ => self.text.len() + SPLITTER.len_utf8(),
+        }
+    }
+
+    /// Add an item, which must not contain the splitter.
+    pub fn item(&mut self, item: &str) -> Result<(), EncodingError> {
+        if let Some(offset) = item.find(SPLITTER) {
+            return Err(EncodingError::Unencodable { offset, chr: SPLITTER });
+        }
+        self.check(item)?;
+        self.start();
+        self.text.push_str(item);
+        Ok(())
+    }
+
+    /// Add `text`, which may contain splitters, as one prefixed item.
+    pub fn prefixed(&mut self, text: &str) -> Result<(), EncodingError> {
+        self.check(text)?;
+        self.start();
+        let mut buf = itoa::Buffer::new();
+        self.text.push_str(buf.format(text.chars().count()));
+        self.text.push(PREFIX_END);
+        self.text.push_str(text);
+        Ok(())
+    }
+
+    /// Add the items written by `write` as one prefixed item.
+    pub fn nested(
+        &mut self,
+        write: impl FnOnce(&mut ItemWriter) -> Result<(), EncodingError>
+    ) -> Result<(), EncodingError> {
+        let mut nested = ItemWriter { text: String::new(), items: 0, table: self.table };
+        write(&mut nested)?;
+        self.prefixed(&nested.finish())
+    }
+
+    /// Write element `index` of a nested value, naming it in errors.
+    pub fn element<T: ScratchField>(&mut self, index: usize, value: T) -> Result<(), EncodingError> {
+        let offset = self.offset();
+        value.write_to(self).map_err(|err| err.in_element(index, offset))
+    }
+
+    /// The text of all items, ready for [`Encoding::try_encode`](super::Encoding::try_encode).
+    pub fn finish(self) -> String {
+        self.text
+    }
+
+    /// Add every item of `other` after those written so far.
+    pub(crate) fn extend(&mut self, other: ItemWriter) {
+        if other.items == 0 {
+            return;
+        }
+        if self.items > 0 {
+            self.text.push(SPLITTER);
+        }
+        self.text.push_str(&other.text);
+        self.items += other.items;
+    }
+
+    fn start(&mut self) {
+        if self.items > 0 {
+            self.text.push(SPLITTER);
+        }
+        self.items += 1;
+    }
+
+    fn check(&self, text: &str) -> Result<(), EncodingError> {
+        let Some(encode) = self.table else {
+            return Ok(());
+        };
+        match text.char_indices().find(|(_, chr)| encode(*chr).is_none()) {
+            Some((offset, chr)) => Err(EncodingError::Unencodable { offset, chr }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Reads items back from the decoded text of a value.
+///
+/// Offsets are in digits of the encoded value. Errors of a prefixed item do not carry its position: the
+/// field or element that was being read is wrapped around them.
+#[derive(Debug, Clone)]
+pub struct ItemReader<'a> {
+    text: &'a str,
+    pos: usize,
+    /// Digits before `pos`, counted from the start of the outermost value.
+    offset: usize,
+    /// Where the prefixed item this reader is for starts, `0` for the outermost value.
+    start: usize,
+    read: usize,
+    done: bool,
+    codec: Codec,
+}
+
+impl<'a> ItemReader<'a> {
+    /// `extended` is whether `text` was decoded with [`ExtendedEncoding`](super::ExtendedEncoding), which
+    /// changes how many digits some characters took.
+    pub fn new(text: &'a str, extended: bool) -> Self {
+        let codec = if extended { Codec::Extended } else { Codec::Table };
+        Self { text, pos: 0, offset: 0, start: 0, read: 0, done: false, codec }
+    }
+
+    /// For `text` decoded with [`CompactEncoding`](super::CompactEncoding).
+    pub fn compact(text: &'a str) -> Self {
+        Self { text, pos: 0, offset: 0, start: 0, read: 0, done: false, codec: Codec::Compact }
+    }
+
+    /// Where the next item starts.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The text that has not been read yet.
+    pub fn remaining(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    /// Whether every item has been read. The empty text holds no items, though reading one from it gives
+    /// the empty item.
+    pub fn is_empty(&self) -> bool {
+        self.done || (self.read == 0 && self.text.is_empty())
+    }
+
+    /// Read a plain item.
+    pub fn item(&mut self) -> Result<&'a str, EncodingError> {
+        if self.done {
+            return Err(EncodingError::MissingItem);
+        }
+
+        let rest = self.remaining();
+        let item = match rest.find(SPLITTER) {
+            Some(end) => {
+                self.advance(end + SPLITTER.len_utf8());
+                &rest[..end]
+            }
+            None => {
+                self.advance(rest.len());
+                self.done = true;
+                rest
+            }
+        };
+        Ok(item)
+    }
+
+    /// Read a prefixed item, returning a reader over the items inside it.
+    pub fn prefixed(&mut self) -> Result<ItemReader<'a>, EncodingError> {
+        if self.is_empty() {
+            return Err(EncodingError::MissingItem);
+        }
+
+        let start = self.offset;
+        let rest = self.remaining();
+        let Some(colon) = rest.find(PREFIX_END) else {
+            return Err(EncodingError::InvalidPrefix);
+        };
+        let length = &rest[..colon];
+        if length.is_empty() || !length.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(EncodingError::InvalidPrefix);
+        }
+        let Some(length) = atoi::atoi::<usize>(length.as_bytes()) else {
+            return Err(EncodingError::InvalidPrefix);
+        };
+
+        let content = &rest[colon + PREFIX_END.len_utf8()..];
+        let Some(end) = content.char_indices().map(|(idx, _)| idx).chain([content.len()]).nth(length) else {
+            return Err(EncodingError::InvalidPrefix);
+        };
+        let after = &content[end..];
+        if !after.is_empty() && !after.starts_with(SPLITTER) {
+            return Err(EncodingError::InvalidPrefix);
+        }
+
+        self.advance(colon + PREFIX_END.len_utf8());
+        let nested = ItemReader {
+            text: &content[..end],
+            pos: 0,
+            offset: self.offset,
+            start,
+            read: 0,
+            done: false,
+            codec: self.codec,
+        };
+        match after.is_empty() {
+            true => {
+                self.advance(end);
+                self.done = true;
+            }
+            false => self.advance(end + SPLITTER.len_utf8()),
+        }
+        Ok(nested)
+    }
+
+    /// Read element `index` of a nested value, naming it in errors.
+    pub fn element<T: ScratchField>(&mut self, index: usize) -> Result<T, EncodingError> {
+        let offset = self.position();
+        T::read_from(self).map_err(|err| err.in_element(index, offset))
+    }
+
+    /// Where the next item starts, counted from the start of this reader's prefixed item.
+    pub(crate) fn position(&self) -> usize {
+        self.offset - self.start
+    }
+
+    /// Fail if any items are left. The offset of the error is counted from the start of this reader's
+    /// prefixed item, like those of elements.
+    pub fn finish(&self) -> Result<(), EncodingError> {
+        match self.is_empty() {
+            true => Ok(()),
+            false => Err(EncodingError::TrailingItems { offset: self.position() }),
+        }
+    }
+
+    /// Step over `len` bytes, ending an item.
+    fn advance(&mut self, len: usize) {
+        let consumed = &self.text[self.pos..self.pos + len];
+        self.offset += consumed.chars().map(|chr| width(chr, self.codec)).sum::<usize>();
+        self.pos += len;
+        self.read += 1;
+    }
+}
+
+macro_rules! impl_plain_field {
+    ($($typ:ty),*) => {
+        $(
+            impl ScratchField for $typ {
+                fn write_to(self, items: &mut ItemWriter) -> Result<(), EncodingError> {
+                    items.item(&self.sb_to_string())
+                }
+
+                fn read_from(items: &mut ItemReader<'_>) -> Result<Self, EncodingError> {
+                    let item = items.item()?.to_string();
+                    match SbStringTo::<$typ>::sb_string_to(&item) {
+                        Some(value) => Ok(value),
+                        None => Err(EncodingError::Parse { expected: stringify!($typ), value: item }),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_plain_field!(String, bool, char, u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+impl_plain_field!(
+    NonZero<u8>, NonZero<u16>, NonZero<u32>, NonZero<u64>, NonZero<u128>, NonZero<usize>,
+    NonZero<i8>, NonZero<i16>, NonZero<i32>, NonZero<i64>, NonZero<i128>, NonZero<isize>
+);
+
+/// Nested values keep their own encoding; its text becomes a prefixed item.
+impl<T: ScratchObject> ScratchField for T {
+    fn write_to(self, items: &mut ItemWriter) -> Result<(), EncodingError> {
+        let numbers = self.try_sb_encode()?;
+        items.prefixed(&T::sb_decode_text(&numbers)?)
+    }
+
+    fn read_from(items: &mut ItemReader<'_>) -> Result<Self, EncodingError> {
+        let nested = items.prefixed()?;
+        // The digits the value was made from. Offsets only line up if the outer value uses the same codes.
+        let numbers = T::sb_encode_text(nested.remaining())?;
+        T::try_from_sb_encoded(&numbers).map_err(|err| shift(err, nested.offset - nested.start))
+    }
+}
+
+impl<T: ScratchField> ScratchField for Vec<T> {
+    fn write_to(self, items: &mut ItemWriter) -> Result<(), EncodingError> {
+        items.nested(|items| {
+            let mut buf = itoa::Buffer::new();
+            items.item(buf.format(self.len()))?;
+            for (index, element) in self.into_iter().enumerate() {
+                items.element(index, element)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn read_from(items: &mut ItemReader<'_>) -> Result<Self, EncodingError> {
+        let mut nested = items.prefixed()?;
+        let length = nested.item()?;
+        let Some(length) = atoi::atoi::<usize>(length.as_bytes()) else {
+            return Err(EncodingError::Parse { expected: "a length", value: length.to_string() });
+        };
+
+        // Every element takes at least a splitter, which bounds what a bad length can allocate.
+        let mut elements = Vec::with_capacity(length.min(nested.remaining().len()));
+        for index in 0..length {
+            elements.push(nested.element(index)?);
+        }
+        nested.finish()?;
+        Ok(elements)
+    }
+}
+
+impl<T: ScratchField> ScratchField for Option<T> {
+    fn write_to(self, items: &mut ItemWriter) -> Result<(), EncodingError> {
+        items.nested(|items| match self {
+            Some(value) => {
+                items.item("1")?;
+                items.element(0, value)
+            }
+            None => items.item("0"),
+        })
+    }
+
+    fn read_from(items: &mut ItemReader<'_>) -> Result<Self, EncodingError> {
+        let mut nested = items.prefixed()?;
+        let value = match nested.item()? {
+            "0" => None,
+            "1" => Some(nested.element(0)?),
+            flag => {
+                return Err(EncodingError::Parse { expected: "0 or 1", value: flag.to_string() });
+            }
+        };
+        nested.finish()?;
+        Ok(value)
+    }
+}
+
+impl<T: ScratchField, const N: usize> ScratchField for [T; N] {
+    fn write_to(self, items: &mut ItemWriter) -> Result<(), EncodingError> {
+        items.nested(|items| {
+            for (index, element) in self.into_iter().enumerate() {
+                items.element(index, element)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn read_from(items: &mut ItemReader<'_>) -> Result<Self, EncodingError> {
+        let mut nested = items.prefixed()?;
+        let mut elements = Vec::with_capacity(N);
+        for index in 0..N {
+            elements.push(nested.element(index)?);
+        }
+        nested.finish()?;
+
+        let Ok(array) = elements.try_into() else {
+            unreachable!("read exactly {N} elements");
+        };
+        Ok(array)
+    }
+}
+
+macro_rules! impl_tuple_field {
+    ($($typ:ident $idx:tt),*) => {
+        impl<$($typ: ScratchField),*> ScratchField for ($($typ,)*) {
+            fn write_to(self, items: &mut ItemWriter) -> Result<(), EncodingError> {
+                items.nested(|items| {
+                    $( items.element($idx, self.$idx)?; )*
+                    Ok(())
+                })
+            }
+
+            fn read_from(items: &mut ItemReader<'_>) -> Result<Self, EncodingError> {
+                let mut nested = items.prefixed()?;
+                let value = ($( nested.element::<$typ>($idx)?, )*);
+                nested.finish()?;
+                Ok(value)
+            }
+        }
+    };
+}
+
+impl_tuple_field!(A 0);
+impl_tuple_field!(A 0, B 1);
+impl_tuple_field!(A 0, B 1, C 2);
+impl_tuple_field!(A 0, B 1, C 2, D 3);
+impl_tuple_field!(A 0, B 1, C 2, D 3, E 4);
+impl_tuple_field!(A 0, B 1, C 2, D 3, E 4, F 5);
+impl_tuple_field!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+impl_tuple_field!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::Encoding;
+
+    #[derive(ScratchObject, Debug, Clone, PartialEq)]
+    struct Player {
+        #[id(0)]
+        name: String,
+        #[id(1)]
+        score: u32,
+    }
+
+    #[derive(ScratchObject, Debug, Clone, PartialEq)]
+    struct Team {
+        #[id(0)]
+        name: String,
+        #[id(1)]
+        players: Vec<String>,
+        #[id(2)]
+        captain: Option<Player>,
+        #[id(3)]
+        position: (i32, i32),
+        #[id(4)]
+        colors: [u8; 3],
+        #[id(5)]
+        members: Vec<Player>,
+    }
+
+    #[derive(ScratchObject, Debug, Clone, PartialEq)]
+    struct Tagged {
+        #[id(0)]
+        name: String,
+        #[id(flatten)]
+        tags: Vec<String>,
+    }
+
+    fn team() -> Team {
+        Team {
+            name: "Red".to_string(),
+            players: vec!["Al".to_string(), "Bo".to_string()],
+            captain: Some(Player { name: "Al".to_string(), score: 3 }),
+            position: (1, -2),
+            colors: [1, 2, 3],
+            members: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn nested_layout() {
+        let numbers = team().try_sb_encode().unwrap();
+        assert_eq!(Encoding::decode(&numbers).unwrap(), "Red•7:2•Al•Bo•8:1•4:Al•3•4:1•-2•5:1•2•3•1:0");
+        assert_eq!(Team::try_from_sb_encoded(&numbers).unwrap(), team());
+    }
+
+    #[test]
+    fn nested_round_trip() {
+        let team = Team {
+            captain: None,
+            members: vec![
+                Player { name: "a b".to_string(), score: 0 },
+                Player { name: String::new(), score: 12 }
+            ],
+            ..team()
+        };
+        let numbers = team.clone().try_sb_encode().unwrap();
+        assert_eq!(Team::try_from_sb_encoded(&numbers).unwrap(), team);
+    }
+
+    #[test]
+    fn flatten() {
+        let tags = vec!["x".to_string(), String::new(), "y".to_string()];
+        let tagged = Tagged { name: "a".to_string(), tags };
+        let numbers = tagged.clone().try_sb_encode().unwrap();
+        assert_eq!(Encoding::decode(&numbers).unwrap(), "a•x••y");
+        assert_eq!(Tagged::try_from_sb_encoded(&numbers).unwrap(), tagged);
+
+        let untagged = Encoding::encode("a").unwrap();
+        assert_eq!(Tagged::try_from_sb_encoded(&untagged).unwrap().tags, Vec::<String>::new());
+
+        let split = Tagged { name: "a".to_string(), tags: vec!["x".to_string(), "y•z".to_string()] };
+        let err = split.try_sb_encode().unwrap_err();
+        let source = Box::new(EncodingError::Unencodable { offset: 1, chr: '•' });
+        assert_eq!(err, EncodingError::Field { id: 2, name: "tags", offset: 8, source });
+    }
+
+    #[test]
+    fn encode_errors_name_the_element() {
+        let team = Team { players: vec!["Al".to_string(), "B•".to_string()], ..team() };
+        let err = team.try_sb_encode().unwrap_err();
+        let source = EncodingError::Unencodable { offset: 1, chr: '•' };
+        // Bytes of "Red•", then of "2•Al•" inside the field.
+        let expected = EncodingError::Field {
+            id: 1,
+            name: "players",
+            offset: 6,
+            source: Box::new(source.in_element(1, 9)),
+        };
+        assert_eq!(err, expected);
+        assert_eq!(err.offset(), Some(16));
+    }
+
+    #[test]
+    fn decode_errors_name_the_element() {
+        let text = "Red•7:2•Al•Bo•8:1•4:Al•3•4:1•x2•5:1•2•3•1:0";
+        let err = Team::try_from_sb_encoded(&Encoding::encode(text).unwrap()).unwrap_err();
+        // 25 characters before the field, then "4:1•" inside it.
+        let source = EncodingError::Parse { expected: "i32", value: "x2".to_string() };
+        let expected = EncodingError::Field {
+            id: 3,
+            name: "position",
+            offset: 50,
+            source: Box::new(source.in_element(1, 8)),
+        };
+        assert_eq!(err, expected);
+
+        // The nested player is decoded on its own; its offsets are shifted to count from its prefix.
+        let text = "Red•7:2•Al•Bo•8:1•4:Al•x•4:1•-2•5:1•2•3•1:0";
+        let err = Team::try_from_sb_encoded(&Encoding::encode(text).unwrap()).unwrap_err();
+        let EncodingError::Field { id: 2, offset: 28, source, .. } = &err else {
+            panic!("{err:?}");
+        };
+        let EncodingError::Element { index: 0, offset: 8, source } = &**source else {
+            panic!("{err:?}");
+        };
+        // "4:Al•" of the player.
+        assert!(matches!(&**source, EncodingError::Field { id: 1, name: "score", offset: 10, .. }), "{err:?}");
+        assert_eq!(err.offset(), Some(46));
+    }
+
+    #[test]
+    fn bad_prefixes() {
+        for text in ["Red•9:2•Al•Bo", "Red•2•Al•Bo", "Red•x:2•Al•Bo", "Red•3:2•Al•Bo"] {
+            let err = Team::try_from_sb_encoded(&Encoding::encode(text).unwrap()).unwrap_err();
+            let EncodingError::Field { id: 1, offset: 8, source, .. } = &err else {
+                panic!("{text}: {err:?}");
+            };
+            assert_eq!(**source, EncodingError::InvalidPrefix, "{text}");
+        }
+    }
+}
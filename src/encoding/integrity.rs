@@ -0,0 +1,164 @@
+//! A length prefix and check digits around encoded values, so one that another client overwrote halfway or
+//! the server cut off is rejected instead of decoding into something that looks valid.
+//!
+//! ```text
+//! <width of the length> <length> <digits> <check digits>
+//! ```
+//!
+//! The length counts the digits of the value. The two check digits are those of ISO 7064 MOD 97-10, as in an
+//! IBAN: read as one number, a sealed value leaves a remainder of 1 when divided by 97. That catches every
+//! changed digit and every swap of two neighbouring digits. [`scratch3`](super::scratch3) has the same
+//! check as blocks.
+//!
+//! ```
+//! use scratchback::encoding::{ integrity, Encoding, EncodingError };
+//!
+//! let numbers = Encoding::encode("hi").unwrap();
+//! let sealed = integrity::seal(&numbers);
+//! assert_eq!(sealed, "14181983");
+//! assert_eq!(integrity::try_verify(&sealed), Ok(numbers.as_str()));
+//!
+//! // Two digits swapped, and the second half cut off:
+//! assert_eq!(integrity::try_verify("14191883"), Err(EncodingError::Checksum));
+//! assert_eq!(integrity::try_verify("141883"), Err(EncodingError::Length { expected: 4, found: 2 }));
+//! ```
+
+use core::ops::Range;
+
+use super::EncodingError;
+
+/// Add the length prefix and check digits to the digits of an encoded value, which are all that
+/// [`try_verify`] accepts.
+pub fn seal(numbers: &str) -> String {
+    let mut length = itoa::Buffer::new();
+    let length = length.format(numbers.len());
+
+    let mut sealed = String::with_capacity(1 + length.len() + numbers.len() + 2);
+    sealed.push(char::from(b'0' + length.len() as u8));
+    sealed.push_str(length);
+    sealed.push_str(numbers);
+    let check = 98 - remainder(sealed.as_bytes()) * 100 % 97;
+    sealed.push(char::from(b'0' + (check / 10) as u8));
+    sealed.push(char::from(b'0' + (check % 10) as u8));
+    sealed
+}
+
+/// The digits of the value in `sealed`, if its length and check digits match.
+pub fn try_verify(sealed: &str) -> Result<&str, EncodingError> {
+    payload(sealed).map(|range| &sealed[range])
+}
+
+pub fn verify(sealed: &str) -> Option<&str> {
+    try_verify(sealed).ok()
+}
+
+/// Where the digits of the value are in `sealed`.
+pub(crate) fn payload(sealed: &str) -> Result<Range<usize>, EncodingError> {
+    let digits = sealed.as_bytes();
+    if !digits.iter().all(u8::is_ascii_digit) {
+        return Err(EncodingError::Parse { expected: "digits", value: sealed.to_string() });
+    }
+
+    let width = match digits.first() {
+        Some(width @ b'1'..=b'9') => (width - b'0') as usize,
+        _ => {
+            return Err(EncodingError::InvalidPrefix);
+        }
+    };
+    let Some(length) = digits.get(1..1 + width).and_then(atoi::atoi::<usize>) else {
+        return Err(EncodingError::InvalidPrefix);
+    };
+
+    let start = 1 + width;
+    let found = digits.len().saturating_sub(start + 2);
+    if found != length {
+        return Err(EncodingError::Length { expected: length, found });
+    }
+    if digits.len() < start + 2 || remainder(digits) != 1 {
+        return Err(EncodingError::Checksum);
+    }
+    Ok(start..start + length)
+}
+
+/// `digits` as a number, modulo 97.
+fn remainder(digits: &[u8]) -> u32 {
+    digits.iter().fold(0, |remainder, digit| (remainder * 10 + digit.wrapping_sub(b'0') as u32) % 97)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::{ Encoding, ScratchObject };
+
+    #[derive(ScratchObject, Debug, PartialEq)]
+    struct Score {
+        #[id(0)]
+        name: String,
+        #[id(1)]
+        score: u32,
+    }
+
+    #[test]
+    fn round_trip() {
+        for text in ["", "a", "hi", &"long•text ".repeat(20)] {
+            let numbers = Encoding::encode(text).unwrap();
+            let sealed = seal(&numbers);
+            assert_eq!(remainder(sealed.as_bytes()), 1);
+            assert_eq!(try_verify(&sealed), Ok(numbers.as_str()));
+        }
+        assert_eq!(seal(""), "1068");
+        assert_eq!(seal(&"11".repeat(50))[..4], *"3100");
+    }
+
+    #[test]
+    fn tampering() {
+        let sealed = seal(&Encoding::encode("Walter•42").unwrap());
+
+        for idx in 0..sealed.len() {
+            for digit in b'0'..=b'9' {
+                let mut changed = sealed.clone().into_bytes();
+                if changed[idx] == digit {
+                    continue;
+                }
+                changed[idx] = digit;
+                let changed = String::from_utf8(changed).unwrap();
+                assert!(verify(&changed).is_none(), "{changed}");
+            }
+        }
+        for idx in 0..sealed.len() - 1 {
+            let mut swapped = sealed.clone().into_bytes();
+            if swapped[idx] == swapped[idx + 1] {
+                continue;
+            }
+            swapped.swap(idx, idx + 1);
+            let swapped = String::from_utf8(swapped).unwrap();
+            assert!(verify(&swapped).is_none(), "{swapped}");
+        }
+    }
+
+    #[test]
+    fn truncated_and_malformed() {
+        let sealed = seal(&Encoding::encode("Walter").unwrap());
+        let truncated = &sealed[..sealed.len() - 4];
+        assert_eq!(try_verify(truncated), Err(EncodingError::Length { expected: 12, found: 8 }));
+        assert_eq!(try_verify(&format!("{sealed}11")), Err(EncodingError::Length { expected: 12, found: 14 }));
+
+        assert_eq!(try_verify(""), Err(EncodingError::InvalidPrefix));
+        assert_eq!(try_verify("0"), Err(EncodingError::InvalidPrefix));
+        assert_eq!(try_verify("3"), Err(EncodingError::InvalidPrefix));
+        assert_eq!(try_verify("10"), Err(EncodingError::Checksum));
+        assert!(matches!(try_verify("1a98"), Err(EncodingError::Parse { .. })));
+    }
+
+    #[test]
+    fn sealed_objects() {
+        let sealed = Score { name: "Al".to_string(), score: 3 }.try_sb_encode_sealed().unwrap();
+        assert_eq!(Score::try_from_sb_sealed(&sealed).unwrap(), Score { name: "Al".to_string(), score: 3 });
+
+        // Offsets count from the start of the sealed value, past the `16` prefix.
+        let bad = seal(&Encoding::encode("Al•x").unwrap());
+        let err = Score::try_from_sb_sealed(&bad).unwrap_err();
+        let source = Box::new(EncodingError::Parse { expected: "u32", value: "x".to_string() });
+        assert_eq!(err, EncodingError::Field { id: 1, name: "score", offset: 8, source });
+    }
+}
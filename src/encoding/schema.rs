@@ -0,0 +1,209 @@
+//! What `#[derive(ScratchObject)]` knows about a type, available at compile time.
+//!
+//! ```
+//! use scratchback::encoding::{ schema::SchemaKind, ScratchObject, ScratchSchema };
+//!
+//! #[derive(ScratchObject)]
+//! struct Player {
+//!     #[id(0)]
+//!     name: String,
+//!     #[id(1)]
+//!     score: u32,
+//! }
+//!
+//! let SchemaKind::Struct { fields, .. } = Player::SCHEMA.kind else { unreachable!() };
+//! assert_eq!(fields[1].name, "score");
+//! assert_eq!(fields[1].ty, "u32");
+//!
+//! println!("{}", Player::SCHEMA);
+//! // Player
+//! //   0  name: String
+//! //   1  score: u32
+//! ```
+
+use core::fmt;
+
+use super::{ CompactEncoding, Encoding, EncodingError, ExtendedEncoding };
+
+/// Implemented by `#[derive(ScratchObject)]` alongside [`ScratchObject`](super::ScratchObject).
+pub trait ScratchSchema {
+    const SCHEMA: Schema;
+}
+
+/// The layout of a `ScratchObject` type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Schema {
+    /// The name of the Rust type.
+    pub name: &'static str,
+    /// Whether it is encoded with [`ExtendedEncoding`].
+    pub extended: bool,
+    /// Whether it is encoded with [`CompactEncoding`].
+    pub compact: bool,
+    /// The characters of the table from `#[scratch(table = MyTable)]`, in code order.
+    pub table: Option<&'static [char]>,
+    /// From `#[scratch(version = n)]`, written as the first item.
+    pub version: Option<u32>,
+    pub kind: SchemaKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchemaKind {
+    /// Items joined with the splitter, each at the position of its id.
+    Struct {
+        /// Sorted by id.
+        fields: &'static [Field],
+        /// The `#[id(flatten)]` field, which holds the items after the last id.
+        flatten: Option<&'static str>,
+    },
+    /// `id • payload`. The payload of a one-value tuple variant is that value's encoding, other variants
+    /// lay their values out as items: tuple elements in order, struct fields at the position of their id.
+    Enum {
+        /// Sorted by id.
+        variants: &'static [Variant],
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Field {
+    pub id: u8,
+    pub name: &'static str,
+    /// The Rust type, as written in the struct.
+    pub ty: &'static str,
+    /// Whether values without this field's item can be decoded, from `#[id(n, default)]` or an `Option`.
+    pub default: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Variant {
+    pub id: u8,
+    pub name: &'static str,
+    /// The Rust type of the payload, as written in the enum: the type of a one-value tuple variant or the
+    /// element types of a longer one. Empty for unit and struct variants.
+    pub ty: &'static str,
+    /// The `#[id]` fields of a struct variant, sorted by id.
+    pub fields: &'static [Field],
+}
+
+impl Schema {
+    /// How many items a struct is encoded as, counting gaps between ids but not the version or flattened
+    /// items. `0` for enums.
+    pub fn items(&self) -> usize {
+        match self.kind {
+            SchemaKind::Struct { fields, .. } => fields.last().map_or(0, |field| field.id as usize + 1),
+            SchemaKind::Enum { .. } => 0,
+        }
+    }
+
+    pub fn field(&self, id: u8) -> Option<&'static Field> {
+        match self.kind {
+            SchemaKind::Struct { fields, .. } => fields.iter().find(|field| field.id == id),
+            SchemaKind::Enum { .. } => None,
+        }
+    }
+
+    pub fn variant(&self, id: u8) -> Option<&'static Variant> {
+        match self.kind {
+            SchemaKind::Struct { .. } => None,
+            SchemaKind::Enum { variants } => variants.iter().find(|variant| variant.id == id),
+        }
+    }
+
+    /// The version `numbers` was encoded with, to tell layouts apart during a rollout. `None` if this type has
+    /// no `#[scratch(version = n)]`.
+    ///
+    /// Only the first item is decoded, so this works on values of another layout too, as long as they have a
+    /// version.
+    pub fn read_version(&self, numbers: &str) -> Result<Option<u32>, EncodingError> {
+        if self.version.is_none() {
+            return Ok(None);
+        }
+
+        let version = match (self.extended, self.compact, self.table) {
+            (true, _, _) => first_item(ExtendedEncoding::chars(numbers))?,
+            (_, true, _) => first_item(CompactEncoding::chars(numbers))?,
+            (_, _, Some(table)) => first_item(chars_with(table, numbers)?)?,
+            _ => first_item(Encoding::chars(numbers))?,
+        };
+        match version.parse::<u32>() {
+            Ok(version) => Ok(Some(version)),
+            Err(_) => Err(EncodingError::Parse { expected: "u32", value: version }),
+        }
+    }
+}
+
+/// The characters before the first splitter.
+fn first_item(chars: impl Iterator<Item = Result<char, EncodingError>>) -> Result<String, EncodingError> {
+    chars.take_while(|chr| *chr != Ok('•')).collect()
+}
+
+/// Like [`TableEncoding::chars`](super::TableEncoding::chars), for a table only known by its characters.
+fn chars_with<'a>(
+    table: &'a [char],
+    numbers: &'a str
+) -> Result<impl Iterator<Item = Result<char, EncodingError>> + 'a, EncodingError> {
+    if !numbers.len().is_multiple_of(2) {
+        return Err(EncodingError::OddLength { length: numbers.len() });
+    }
+    let chars = numbers
+        .as_bytes()
+        .chunks_exact(2)
+        .enumerate()
+        .map(|(i, pair)| {
+            let chr = match pair {
+                [tens @ b'0'..=b'9', ones @ b'0'..=b'9'] => table.get(((tens - b'0') * 10 + ones - b'0') as usize),
+                _ => None,
+            };
+            chr.copied().ok_or_else(|| EncodingError::InvalidCode {
+                offset: i * 2,
+                pair: String::from_utf8_lossy(pair).into_owned(),
+            })
+        });
+    Ok(chars)
+}
+
+/// One line per field or variant, with its id.
+impl fmt::Display for Schema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(version) = self.version {
+            write!(f, " v{version}")?;
+        }
+        if self.extended {
+            write!(f, " (extended)")?;
+        }
+        if self.compact {
+            write!(f, " (compact)")?;
+        }
+        if self.table.is_some() {
+            write!(f, " (custom table)")?;
+        }
+        match self.kind {
+            SchemaKind::Struct { fields, flatten } => {
+                for field in fields {
+                    write!(f, "\n  {}  {}: {}", field.id, field.name, field.ty)?;
+                    if field.default {
+                        write!(f, " (default)")?;
+                    }
+                }
+                if let Some(flatten) = flatten {
+                    write!(f, "\n  ..  {flatten}: Vec<String>")?;
+                }
+            }
+            SchemaKind::Enum { variants } => {
+                for variant in variants {
+                    write!(f, "\n  {}  {}", variant.id, variant.name)?;
+                    if !variant.ty.is_empty() {
+                        write!(f, "({})", variant.ty)?;
+                    }
+                    for field in variant.fields {
+                        write!(f, "\n      {}  {}: {}", field.id, field.name, field.ty)?;
+                        if field.default {
+                            write!(f, " (default)")?;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
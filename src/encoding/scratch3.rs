@@ -0,0 +1,1234 @@
+//! Scratch 3 custom blocks that encode and decode `ScratchObject` types inside a project.
+//!
+//! The blocks are generated from the [`Schema`] of each type, so they always match the ids the derive macro
+//! uses. Save the sprite and drag it into the project (or copy its scripts into another sprite):
+//!
+//! ```no_run
+//! use scratchback::encoding::{ scratch3::Sprite, ScratchObject };
+//!
+//! #[derive(ScratchObject)]
+//! struct Player {
+//!     #[id(0)]
+//!     name: String,
+//!     #[id(1)]
+//!     score: u32,
+//! }
+//!
+//! std::fs::write("codec.sprite3", Sprite::new("codec").with::<Player>().to_sprite3()).unwrap();
+//! ```
+//!
+//! For every struct the sprite gets a variable per field, named `Player.name`, a list for the
+//! `#[id(flatten)]` field if there is one, and:
+//!
+//! - `decode Player (numbers)`, which sets the field variables from an encoded value.
+//! - `encode Player`, which sets `Player encoded` from the field variables.
+//!
+//! For every enum it gets `decode Cmd (numbers)`, which sets `Cmd variant` to the variant id and
+//! `Cmd payload` to the still encoded payload, and `encode Cmd (variant) (payload)`.
+//!
+//! Scratch compares text without regard to case, so the encoder tells `a` from `A` by switching the sprite
+//! to its costume named after the letter: there is one per capital letter. The encoder cannot see code
+//! points, so it leaves out characters outside the [`EncodingTable`] (or the compact table) and sets
+//! `sb error` to them; every `encode` block empties it first. The decoder reads
+//! [`ExtendedEncoding`](super::ExtendedEncoding) escapes from the `sb extra codes` and `sb extra chars` lists.
+//!
+//! Types with `#[scratch(compact)]` use `sb compact encode` and `sb compact decode` instead, which look codes
+//! up in the `sb compact codes` and `sb compact` lists, see [`CompactEncoding`](super::CompactEncoding).
+//! There are no blocks for other tables yet: types with `#[scratch(table = ...)]` get the blocks of the
+//! [`EncodingTable`], which only read their values if both tables give the same characters the same codes.
+//!
+//! [`Sprite::sealed`] adds the [`integrity`](super::integrity) check: `sb verify (sealed)` sets `sb valid` to
+//! `1` and `sb verified` to the digits of the value if its length and check digits match, and to `0` and
+//! nothing otherwise. `sb seal (numbers)` sets `sb sealed` to a value Rust can verify.
+//!
+//! The decoder splits values at every splitter, so it only reads types whose fields are plain items:
+//! strings, numbers and booleans. [Prefixed](super::field) fields would be cut apart.
+
+use serde_json::{ json, Map, Value };
+
+use super::{ schema::SchemaKind, CompactEncoding, CompactTable, Encoding, EncodingTable, Schema, ScratchSchema };
+
+/// An empty costume, shared by every costume of the sprite.
+const COSTUME_SVG: &str =
+    "<svg xmlns=\"http://www.w3.org/2000/svg\" version=\"1.1\" width=\"2\" height=\"2\" viewBox=\"0 0 2 2\"></svg>";
+/// The MD5 of [`COSTUME_SVG`], which Scratch uses as its asset id.
+const COSTUME_ID: &str = "e13a1ea0e8b5b7b331c4aad6423d91d8";
+
+/// A sprite holding the encoder and decoder blocks of some `ScratchObject` types.
+pub struct Sprite {
+    name: String,
+    schemas: Vec<Schema>,
+    sealed: bool,
+}
+
+impl Sprite {
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        Self { name: name.into(), schemas: Vec::new(), sealed: false }
+    }
+
+    /// Add `sb seal` and `sb verify`, for values of
+    /// [`try_sb_encode_sealed`](super::ScratchObject::try_sb_encode_sealed).
+    pub fn sealed(mut self) -> Self {
+        self.sealed = true;
+        self
+    }
+
+    /// Add the blocks of a type.
+    pub fn with<T: ScratchSchema>(self) -> Self {
+        self.schema(T::SCHEMA)
+    }
+
+    pub fn schema(mut self, schema: Schema) -> Self {
+        self.schemas.push(schema);
+        self
+    }
+
+    /// The sprite as a target of `project.json`, which is also the `sprite.json` of a `.sprite3`.
+    pub fn to_json(&self) -> Value {
+        let mut builder = Builder::default();
+        let helpers = Helpers::new(
+            &mut builder,
+            self.schemas.iter().any(|schema| schema.extended),
+            self.schemas.iter().any(|schema| schema.compact)
+        );
+        for schema in &self.schemas {
+            match schema.kind {
+                SchemaKind::Struct { .. } => struct_scripts(&mut builder, &helpers, schema),
+                SchemaKind::Enum { .. } => enum_scripts(&mut builder, &helpers, schema),
+            }
+        }
+        if self.sealed {
+            integrity_scripts(&mut builder);
+        }
+
+        let costume = |name: &str| {
+            json!({
+                "name": name,
+                "bitmapResolution": 1,
+                "dataFormat": "svg",
+                "assetId": COSTUME_ID,
+                "md5ext": format!("{COSTUME_ID}.svg"),
+                "rotationCenterX": 1,
+                "rotationCenterY": 1,
+            })
+        };
+        let costumes = std::iter::once(costume("blank"))
+            .chain(('A'..='Z').map(|letter| costume(&letter.to_string())))
+            .collect::<Vec<_>>();
+
+        json!({
+            "isStage": false,
+            "name": self.name,
+            "variables": builder.variables,
+            "lists": builder.lists,
+            "broadcasts": {},
+            "blocks": builder.blocks,
+            "comments": {},
+            "currentCostume": 0,
+            "costumes": costumes,
+            "sounds": [],
+            "volume": 100,
+            "layerOrder": 1,
+            "visible": false,
+            "x": 0,
+            "y": 0,
+            "size": 100,
+            "direction": 90,
+            "draggable": false,
+            "rotationStyle": "all around",
+        })
+    }
+
+    /// A `.sprite3` file, ready to be uploaded in the Scratch editor.
+    pub fn to_sprite3(&self) -> Vec<u8> {
+        let sprite = serde_json::to_vec(&self.to_json()).unwrap();
+        let costume = format!("{COSTUME_ID}.svg");
+        zip(&[("sprite.json", &sprite), (&costume, COSTUME_SVG.as_bytes())])
+    }
+}
+
+/// The blocks shared by every type.
+struct Helpers {
+    table: Var,
+    items: Var,
+    encoded: Var,
+    /// The last character `sb encode` or `sb compact encode` could not encode.
+    error: Var,
+    /// `sb encode (text)` appends the encoded text to `sb encoded`.
+    encode: Procedure,
+    /// `sb decode (numbers)` decodes the items of a value into `sb items`.
+    decode: Procedure,
+    /// `sb compact encode (text)` and `sb compact decode (numbers)`, the same for [`CompactEncoding`].
+    compact: Option<(Procedure, Procedure)>,
+}
+
+impl Helpers {
+    fn new(builder: &mut Builder, extended: bool, compact: bool) -> Self {
+        let table = builder.list("sb table", EncodingTable::TABLE[1..].iter().map(char::to_string).collect());
+        let items = builder.list("sb items", Vec::new());
+        let encoded = builder.variable("sb encoded");
+        let error = builder.variable("sb error");
+        let i = builder.variable("sb i");
+        let code = builder.variable("sb code");
+        let item = builder.variable("sb item");
+
+        let text = || argument("text");
+        // Sets `sb code` to the index of every character in the table, then runs `append` for those it has.
+        let for_each_code = |append: Vec<Block>| {
+            let body = vec![
+                set(&item, letter(&i, text())),
+                set(&code, item_num(&item, &table)),
+                // Lowercase and capital letters both find the lowercase one.
+                if_then(
+                    and(gt(&code, "10"), lt(&code, "37")),
+                    vec![
+                        switch_costume(Input::Text("blank".to_string())),
+                        switch_costume(Input::from(&item)),
+                        if_then(gt(costume_number(), "1"), vec![change(&code, num("26"))])
+                    ]
+                ),
+                if_else(equals(&code, "0"), vec![set(&error, &item)], append),
+                change(&i, num("1"))
+            ];
+            vec![set(&i, num("1")), repeat(length(text()), body)]
+        };
+
+        let encode = Procedure::new("sb encode %s", &["text"]);
+        builder.script(
+            encode.define(
+                for_each_code(
+                    vec![
+                        if_then(lt(&code, "10"), vec![set(&code, join("0", &code))]),
+                        set(&encoded, join(&encoded, &code))
+                    ]
+                )
+            )
+        );
+
+        let compact = compact.then(|| {
+            let chars = builder.list("sb compact", CompactTable::TABLE.iter().map(char::to_string).collect());
+            // The compact code of every character of `sb table`.
+            let codes = builder.list(
+                "sb compact codes",
+                EncodingTable::TABLE[1..]
+                    .iter()
+                    .map(|chr| CompactEncoding::encode(&chr.to_string()).unwrap_or_default())
+                    .collect()
+            );
+
+            let encode = Procedure::new("sb compact encode %s", &["text"]);
+            builder.script(
+                encode.define(
+                    for_each_code(
+                        vec![
+                            set(&code, Block::new("data_itemoflist").input("INDEX", &code).list(&codes)),
+                            // Characters of the table without a compact code.
+                            if_else(
+                                equals(&code, ""),
+                                vec![set(&error, &item)],
+                                vec![set(&encoded, join(&encoded, &code))]
+                            )
+                        ]
+                    )
+                )
+            );
+
+            let decode = Procedure::new("sb compact decode %s", &["numbers"]);
+            let numbers = || argument("numbers");
+            builder.script(
+                decode.define(
+                    vec![
+                        Block::new("data_deletealloflist").list(&items),
+                        set(&item, Input::Text(String::new())),
+                        set(&i, num("1")),
+                        repeat_until(
+                            gt(&i, length(numbers())),
+                            vec![
+                                set(&code, letter(&i, numbers())),
+                                change(&i, num("1")),
+                                // `8` and `9` start two-digit codes, `0` three-digit ones.
+                                if_then(
+                                    gt(&code, "7"),
+                                    vec![
+                                        set(&code, sub(join(&code, letter(&i, numbers())), num("72"))),
+                                        change(&i, num("1"))
+                                    ]
+                                ),
+                                if_then(
+                                    equals(&code, "0"),
+                                    vec![
+                                        set(
+                                            &code,
+                                            add(
+                                                join(letter(&i, numbers()), letter(add(&i, num("1")), numbers())),
+                                                num("28")
+                                            )
+                                        ),
+                                        change(&i, num("2"))
+                                    ]
+                                ),
+                                if_else(
+                                    equals(&code, "7"),
+                                    vec![add_to(&item, &items), set(&item, Input::Text(String::new()))],
+                                    vec![
+                                        set(
+                                            &item,
+                                            join(&item, Block::new("data_itemoflist").input("INDEX", &code).list(&chars))
+                                        )
+                                    ]
+                                )
+                            ]
+                        ),
+                        if_then(gt(length(numbers()), "0"), vec![add_to(&item, &items)])
+                    ]
+                )
+            );
+            (encode, decode)
+        });
+
+        let decode = Procedure::new("sb decode %s", &["numbers"]);
+        let numbers = || argument("numbers");
+        let table_char = set(&item, join(&item, Block::new("data_itemoflist").input("INDEX", &code).list(&table)));
+        let char_or_escape = if extended {
+            let width = builder.variable("sb width");
+            let extra_codes = builder.list("sb extra codes", Vec::new());
+            let extra_chars = builder.list("sb extra chars", Vec::new());
+            let code_point = || add(&code, num("0"));
+            vec![
+                if_else(
+                    or(equals(&code, "98"), equals(&code, "99")),
+                    vec![
+                        set(&width, num("6")),
+                        if_then(equals(&code, "99"), vec![set(&width, num("8"))]),
+                        set(&code, Input::Text(String::new())),
+                        repeat(&width, vec![set(&code, join(&code, letter(&i, numbers()))), change(&i, num("1"))]),
+                        if_else(
+                            Block::new("data_listcontainsitem").input("ITEM", code_point()).list(&extra_codes),
+                            vec![
+                                set(
+                                    &item,
+                                    join(
+                                        &item,
+                                        Block::new("data_itemoflist")
+                                            .input("INDEX", item_num(code_point(), &extra_codes))
+                                            .list(&extra_chars)
+                                    )
+                                )
+                            ],
+                            vec![set(&item, join(&item, "�"))]
+                        )
+                    ],
+                    vec![table_char]
+                )
+            ]
+        } else {
+            vec![table_char]
+        };
+        builder.script(
+            decode.define(
+                vec![
+                    Block::new("data_deletealloflist").list(&items),
+                    set(&item, Input::Text(String::new())),
+                    set(&i, num("1")),
+                    repeat_until(
+                        gt(&i, length(numbers())),
+                        vec![
+                            set(&code, join(letter(&i, numbers()), letter(add(&i, num("1")), numbers()))),
+                            change(&i, num("2")),
+                            if_else(
+                                equals(&code, "97"),
+                                vec![add_to(&item, &items), set(&item, Input::Text(String::new()))],
+                                char_or_escape
+                            )
+                        ]
+                    ),
+                    // A trailing splitter still ends an (empty) item.
+                    if_then(gt(length(numbers()), "0"), vec![add_to(&item, &items)])
+                ]
+            )
+        );
+
+        Self { table, items, encoded, error, encode, decode, compact }
+    }
+}
+
+/// `sb seal (numbers)` and `sb verify (sealed)`, see [`integrity`](super::integrity).
+fn integrity_scripts(builder: &mut Builder) {
+    let sealed = builder.variable("sb sealed");
+    let verified = builder.variable("sb verified");
+    let valid = builder.variable("sb valid");
+    let i = builder.variable("sb i");
+    let code = builder.variable("sb code");
+    let width = builder.variable("sb width");
+    let length_var = builder.variable("sb length");
+
+    // Sets `sb code` to the digits of `text` as a number, modulo 97.
+    let remainder = |text: &dyn Fn() -> Input| {
+        vec![
+            set(&code, num("0")),
+            set(&i, num("1")),
+            repeat(
+                length(text()),
+                vec![
+                    set(&code, modulo(add(mul(&code, num("10")), letter(&i, text())), num("97"))),
+                    change(&i, num("1"))
+                ]
+            )
+        ]
+    };
+
+    let numbers = || argument("numbers");
+    let seal = Procedure::new("sb seal %s", &["numbers"]);
+    let mut body = vec![set(&sealed, join(join(length(length(numbers())), length(numbers())), numbers()))];
+    body.extend(remainder(&|| Input::from(&sealed)));
+    body.extend([
+        set(&code, sub(num("98"), modulo(mul(&code, num("100")), num("97")))),
+        if_then(lt(&code, "10"), vec![set(&code, join("0", &code))]),
+        set(&sealed, join(&sealed, &code)),
+    ]);
+    builder.script(seal.define(body));
+
+    let value = || argument("sealed");
+    let verify = Procedure::new("sb verify %s", &["sealed"]);
+    let mut body = vec![set(&valid, num("0")), set(&verified, Input::Text(String::new()))];
+    body.extend(remainder(&|| value().into()));
+    body.extend([
+        set(&width, letter(num("1"), value())),
+        set(&length_var, Input::Text(String::new())),
+        set(&i, num("2")),
+        repeat(&width, vec![set(&length_var, join(&length_var, letter(&i, value()))), change(&i, num("1"))]),
+        // The digits of the value start at `sb i`, right after the length.
+        if_then(
+            and(
+                and(equals(&code, "1"), gt(&width, "0")),
+                equals(length(value()), add(add(&length_var, &width), num("3")))
+            ),
+            vec![
+                set(&valid, num("1")),
+                repeat(&length_var, vec![set(&verified, join(&verified, letter(&i, value()))), change(&i, num("1"))])
+            ]
+        ),
+    ]);
+    builder.script(verify.define(body));
+}
+
+fn struct_scripts(builder: &mut Builder, helpers: &Helpers, schema: &Schema) {
+    let SchemaKind::Struct { fields, flatten } = schema.kind else {
+        return;
+    };
+    let k = builder.variable("sb k");
+    let rest = flatten.map(|flatten| builder.list(&format!("{}.{flatten}", schema.name), Vec::new()));
+    let vars = fields
+        .iter()
+        .map(|field| (field.id, builder.variable(&format!("{}.{}", schema.name, field.name))))
+        .collect::<Vec<_>>();
+
+    // Items are 1-indexed in Scratch, and the version comes first.
+    let first = if schema.version.is_some() { 2 } else { 1 };
+    let (encode_text, decode_text, splitter_encoded) = match &helpers.compact {
+        Some((encode, decode)) if schema.compact => (encode, decode, CompactEncoding::SPLITTER_ENCODED),
+        _ => (&helpers.encode, &helpers.decode, Encoding::SPLITTER_ENCODED),
+    };
+
+    let decode = Procedure::new(&format!("decode {} %s", schema.name), &["numbers"]);
+    let mut body = vec![decode_text.call(vec![argument("numbers").into()])];
+    // Items missing from older values read as empty.
+    for (id, var) in &vars {
+        let value = Block::new("data_itemoflist")
+            .input("INDEX", num(&(*id as usize + first).to_string()))
+            .list(&helpers.items);
+        body.push(set(var, value));
+    }
+    if let Some(rest) = &rest {
+        body.extend([
+            Block::new("data_deletealloflist").list(rest),
+            set(&k, num(&(schema.items() + first).to_string())),
+            repeat_until(
+                gt(&k, Block::new("data_lengthoflist").list(&helpers.items)),
+                vec![
+                    add_to(Block::new("data_itemoflist").input("INDEX", &k).list(&helpers.items), rest),
+                    change(&k, num("1"))
+                ]
+            ),
+        ]);
+    }
+    builder.script(decode.define(body));
+
+    let result = builder.variable(&format!("{} encoded", schema.name));
+    let encode = Procedure::new(&format!("encode {}", schema.name), &[]);
+    let mut body = vec![
+        set(&helpers.encoded, Input::Text(String::new())),
+        set(&helpers.error, Input::Text(String::new())),
+    ];
+    if let Some(version) = schema.version {
+        body.push(encode_text.call(vec![Input::Text(version.to_string())]));
+    }
+    for idx in 0..schema.items() {
+        if idx > 0 || schema.version.is_some() {
+            body.push(set(&helpers.encoded, join(&helpers.encoded, splitter_encoded)));
+        }
+        if let Some((_, var)) = vars.iter().find(|(id, _)| *id as usize == idx) {
+            body.push(encode_text.call(vec![var.into()]));
+        }
+    }
+    if let Some(rest) = &rest {
+        let splitter = set(&helpers.encoded, join(&helpers.encoded, splitter_encoded));
+        body.extend([
+            set(&k, num("1")),
+            repeat(
+                Block::new("data_lengthoflist").list(rest),
+                vec![
+                    if schema.items() > 0 || schema.version.is_some() {
+                        splitter
+                    } else {
+                        if_then(gt(&k, "1"), vec![splitter])
+                    },
+                    encode_text.call(vec![Block::new("data_itemoflist").input("INDEX", &k).list(rest).into()]),
+                    change(&k, num("1"))
+                ]
+            ),
+        ]);
+    }
+    body.push(set(&result, &helpers.encoded));
+    builder.script(encode.define(body));
+}
+
+fn enum_scripts(builder: &mut Builder, helpers: &Helpers, schema: &Schema) {
+    let i = builder.variable("sb i");
+    let code = builder.variable("sb code");
+    let variant = builder.variable(&format!("{} variant", schema.name));
+    let payload = builder.variable(&format!("{} payload", schema.name));
+    let numbers = || argument("numbers");
+
+    let compact = helpers.compact.as_ref().filter(|_| schema.compact);
+    // Reads an item up to the next splitter, appending its characters to `target` if there is one.
+    let read_item = |target: Option<&Var>| match compact {
+        // Ids and versions are digits, whose compact codes are `000` to `009`, and the splitter is `7`.
+        Some(_) => {
+            let digit = target.map(|target| set(target, join(target, letter(add(&i, num("2")), numbers()))));
+            repeat_until(
+                or(equals(&code, "7"), gt(&i, length(numbers()))),
+                vec![
+                    set(&code, letter(&i, numbers())),
+                    if_else(
+                        equals(&code, "7"),
+                        vec![change(&i, num("1"))],
+                        digit.into_iter().chain([change(&i, num("3"))]).collect()
+                    )
+                ]
+            )
+        }
+        None => {
+            let chr = Block::new("data_itemoflist").input("INDEX", &code).list(&helpers.table);
+            let append = target.map(|target| set(target, join(target, chr)));
+            repeat_until(
+                or(equals(&code, "97"), gt(&i, length(numbers()))),
+                vec![
+                    set(&code, join(letter(&i, numbers()), letter(add(&i, num("1")), numbers()))),
+                    change(&i, num("2")),
+                    if_then(
+                        Block::new("operator_not").condition("OPERAND", equals(&code, "97")),
+                        Vec::from_iter(append)
+                    )
+                ]
+            )
+        }
+    };
+
+    let decode = Procedure::new(&format!("decode {} %s", schema.name), &["numbers"]);
+    let mut body = vec![
+        set(&variant, Input::Text(String::new())),
+        set(&payload, Input::Text(String::new())),
+        set(&code, Input::Text(String::new())),
+        set(&i, num("1")),
+    ];
+    // The version comes first, and is skipped like that of a struct.
+    if schema.version.is_some() {
+        body.extend([read_item(None), set(&code, Input::Text(String::new()))]);
+    }
+    body.extend([
+        read_item(Some(&variant)),
+        repeat_until(
+            gt(&i, length(numbers())),
+            vec![set(&payload, join(&payload, letter(&i, numbers()))), change(&i, num("1"))]
+        ),
+    ]);
+    builder.script(decode.define(body));
+
+    let (encode_text, splitter) = match compact {
+        Some((encode, _)) => (encode, CompactEncoding::SPLITTER_ENCODED),
+        None => (&helpers.encode, Encoding::SPLITTER_ENCODED),
+    };
+    let result = builder.variable(&format!("{} encoded", schema.name));
+    let encode = Procedure::new(&format!("encode {} %s %s", schema.name), &["variant", "payload"]);
+    let mut body = vec![
+        set(&helpers.encoded, Input::Text(String::new())),
+        set(&helpers.error, Input::Text(String::new())),
+    ];
+    if let Some(version) = schema.version {
+        body.extend([
+            encode_text.call(vec![Input::Text(version.to_string())]),
+            set(&helpers.encoded, join(&helpers.encoded, splitter)),
+        ]);
+    }
+    body.extend([
+        encode_text.call(vec![argument("variant").into()]),
+        set(&result, join(&helpers.encoded, join(splitter, argument("payload")))),
+    ]);
+    builder.script(encode.define(body));
+}
+
+/// A variable or list of the sprite.
+#[derive(Clone)]
+struct Var {
+    name: String,
+    id: String,
+}
+
+/// A block before it is given an id.
+struct Block {
+    opcode: &'static str,
+    inputs: Vec<(String, Input)>,
+    fields: Vec<(&'static str, Value)>,
+    mutation: Option<Value>,
+    shadow: bool,
+}
+
+enum Input {
+    Text(String),
+    Number(String),
+    Variable(Var),
+    /// A reporter in a text or number slot.
+    Reporter(Box<Block>),
+    /// A boolean reporter, which has no shadow.
+    Condition(Box<Block>),
+    Stack(Vec<Block>),
+    /// A menu or a procedure prototype, maybe covered by a variable or reporter.
+    Shadow(Box<Block>, Option<Box<Input>>),
+}
+
+impl From<Block> for Input {
+    fn from(block: Block) -> Self {
+        Self::Reporter(Box::new(block))
+    }
+}
+
+impl From<&Var> for Input {
+    fn from(var: &Var) -> Self {
+        Self::Variable(var.clone())
+    }
+}
+
+impl From<&str> for Input {
+    fn from(text: &str) -> Self {
+        Self::Text(text.to_string())
+    }
+}
+
+impl Block {
+    fn new(opcode: &'static str) -> Self {
+        Self { opcode, inputs: Vec::new(), fields: Vec::new(), mutation: None, shadow: false }
+    }
+
+    fn input<S: Into<String>, I: Into<Input>>(mut self, name: S, input: I) -> Self {
+        self.inputs.push((name.into(), input.into()));
+        self
+    }
+
+    fn condition(mut self, name: &str, condition: Block) -> Self {
+        self.inputs.push((name.to_string(), Input::Condition(Box::new(condition))));
+        self
+    }
+
+    fn stack(mut self, name: &str, stack: Vec<Block>) -> Self {
+        self.inputs.push((name.to_string(), Input::Stack(stack)));
+        self
+    }
+
+    fn field(mut self, name: &'static str, value: Value) -> Self {
+        self.fields.push((name, value));
+        self
+    }
+
+    fn variable(self, var: &Var) -> Self {
+        self.field("VARIABLE", json!([var.name, var.id]))
+    }
+
+    fn list(self, list: &Var) -> Self {
+        self.field("LIST", json!([list.name, list.id]))
+    }
+}
+
+fn num(n: &str) -> Input {
+    Input::Number(n.to_string())
+}
+
+fn set<I: Into<Input>>(var: &Var, value: I) -> Block {
+    Block::new("data_setvariableto").input("VALUE", value).variable(var)
+}
+
+fn change<I: Into<Input>>(var: &Var, by: I) -> Block {
+    Block::new("data_changevariableby").input("VALUE", by).variable(var)
+}
+
+fn join<A: Into<Input>, B: Into<Input>>(a: A, b: B) -> Block {
+    Block::new("operator_join").input("STRING1", a).input("STRING2", b)
+}
+
+fn letter<A: Into<Input>, B: Into<Input>>(index: A, text: B) -> Block {
+    Block::new("operator_letter_of").input("LETTER", index).input("STRING", text)
+}
+
+fn length<I: Into<Input>>(text: I) -> Block {
+    Block::new("operator_length").input("STRING", text)
+}
+
+fn add<A: Into<Input>, B: Into<Input>>(a: A, b: B) -> Block {
+    Block::new("operator_add").input("NUM1", a).input("NUM2", b)
+}
+
+fn sub<A: Into<Input>, B: Into<Input>>(a: A, b: B) -> Block {
+    Block::new("operator_subtract").input("NUM1", a).input("NUM2", b)
+}
+
+fn mul<A: Into<Input>, B: Into<Input>>(a: A, b: B) -> Block {
+    Block::new("operator_multiply").input("NUM1", a).input("NUM2", b)
+}
+
+fn modulo<A: Into<Input>, B: Into<Input>>(a: A, b: B) -> Block {
+    Block::new("operator_mod").input("NUM1", a).input("NUM2", b)
+}
+
+fn gt<A: Into<Input>, B: Into<Input>>(a: A, b: B) -> Block {
+    Block::new("operator_gt").input("OPERAND1", a).input("OPERAND2", b)
+}
+
+fn lt<A: Into<Input>, B: Into<Input>>(a: A, b: B) -> Block {
+    Block::new("operator_lt").input("OPERAND1", a).input("OPERAND2", b)
+}
+
+fn equals<A: Into<Input>, B: Into<Input>>(a: A, b: B) -> Block {
+    Block::new("operator_equals").input("OPERAND1", a).input("OPERAND2", b)
+}
+
+fn and(a: Block, b: Block) -> Block {
+    Block::new("operator_and").condition("OPERAND1", a).condition("OPERAND2", b)
+}
+
+fn or(a: Block, b: Block) -> Block {
+    Block::new("operator_or").condition("OPERAND1", a).condition("OPERAND2", b)
+}
+
+fn item_num<I: Into<Input>>(item: I, list: &Var) -> Block {
+    Block::new("data_itemnumoflist").input("ITEM", item).list(list)
+}
+
+fn add_to<I: Into<Input>>(item: I, list: &Var) -> Block {
+    Block::new("data_addtolist").input("ITEM", item).list(list)
+}
+
+fn repeat<I: Into<Input>>(times: I, body: Vec<Block>) -> Block {
+    Block::new("control_repeat").input("TIMES", times).stack("SUBSTACK", body)
+}
+
+fn repeat_until(condition: Block, body: Vec<Block>) -> Block {
+    Block::new("control_repeat_until").condition("CONDITION", condition).stack("SUBSTACK", body)
+}
+
+fn if_then(condition: Block, body: Vec<Block>) -> Block {
+    Block::new("control_if").condition("CONDITION", condition).stack("SUBSTACK", body)
+}
+
+fn if_else(condition: Block, then: Vec<Block>, otherwise: Vec<Block>) -> Block {
+    Block::new("control_if_else")
+        .condition("CONDITION", condition)
+        .stack("SUBSTACK", then)
+        .stack("SUBSTACK2", otherwise)
+}
+
+fn switch_costume(costume: Input) -> Block {
+    let mut menu = Block::new("looks_costume").field("COSTUME", json!(["blank", null]));
+    menu.shadow = true;
+    let cover = match costume {
+        Input::Text(name) => {
+            menu.fields = vec![("COSTUME", json!([name, null]))];
+            None
+        }
+        cover => Some(Box::new(cover)),
+    };
+    Block::new("looks_switchcostumeto").input("COSTUME", Input::Shadow(Box::new(menu), cover))
+}
+
+fn costume_number() -> Block {
+    Block::new("looks_costumenumbername").field("NUMBER_NAME", json!(["number", null]))
+}
+
+fn argument(name: &str) -> Block {
+    Block::new("argument_reporter_string_number").field("VALUE", json!([name, null]))
+}
+
+/// A custom block. Every generated one runs without screen refresh.
+struct Procedure {
+    proccode: String,
+    arguments: Vec<String>,
+}
+
+impl Procedure {
+    fn new(proccode: &str, arguments: &[&str]) -> Self {
+        Self { proccode: proccode.to_string(), arguments: arguments.iter().map(|arg| arg.to_string()).collect() }
+    }
+
+    fn argument_ids(&self) -> Vec<String> {
+        self.arguments
+            .iter()
+            .map(|arg| format!("{}:{arg}", self.proccode))
+            .collect()
+    }
+
+    /// The definition hat followed by its body.
+    fn define(&self, body: Vec<Block>) -> Vec<Block> {
+        let mut prototype = Block::new("procedures_prototype");
+        prototype.shadow = true;
+        prototype.mutation = Some(
+            json!({
+                "tagName": "mutation",
+                "children": [],
+                "proccode": self.proccode,
+                "argumentids": serde_json::to_string(&self.argument_ids()).unwrap(),
+                "argumentnames": serde_json::to_string(&self.arguments).unwrap(),
+                "argumentdefaults": serde_json::to_string(&vec![""; self.arguments.len()]).unwrap(),
+                "warp": "true",
+            })
+        );
+        for (id, name) in self.argument_ids().into_iter().zip(&self.arguments) {
+            let mut reporter = argument(name);
+            reporter.shadow = true;
+            prototype = prototype.input(id, Input::Shadow(Box::new(reporter), None));
+        }
+
+        let definition = Block::new("procedures_definition").input(
+            "custom_block",
+            Input::Shadow(Box::new(prototype), None)
+        );
+        std::iter::once(definition).chain(body).collect()
+    }
+
+    fn call(&self, arguments: Vec<Input>) -> Block {
+        let mut call = Block::new("procedures_call");
+        call.mutation = Some(
+            json!({
+                "tagName": "mutation",
+                "children": [],
+                "proccode": self.proccode,
+                "argumentids": serde_json::to_string(&self.argument_ids()).unwrap(),
+                "warp": "true",
+            })
+        );
+        for (id, argument) in self.argument_ids().into_iter().zip(arguments) {
+            call = call.input(id, argument);
+        }
+        call
+    }
+}
+
+/// Gives blocks, variables and lists their ids.
+#[derive(Default)]
+struct Builder {
+    blocks: Map<String, Value>,
+    variables: Map<String, Value>,
+    lists: Map<String, Value>,
+    next: u32,
+    scripts: u32,
+}
+
+impl Builder {
+    fn id(&mut self) -> String {
+        self.next += 1;
+        format!("sb{}", self.next)
+    }
+
+    fn variable(&mut self, name: &str) -> Var {
+        if let Some((id, _)) = self.variables.iter().find(|(_, value)| value[0] == name) {
+            return Var { name: name.to_string(), id: id.clone() };
+        }
+        let id = self.id();
+        self.variables.insert(id.clone(), json!([name, ""]));
+        Var { name: name.to_string(), id }
+    }
+
+    fn list(&mut self, name: &str, items: Vec<String>) -> Var {
+        if let Some((id, _)) = self.lists.iter().find(|(_, value)| value[0] == name) {
+            return Var { name: name.to_string(), id: id.clone() };
+        }
+        let id = self.id();
+        self.lists.insert(id.clone(), json!([name, items]));
+        Var { name: name.to_string(), id }
+    }
+
+    /// Add a top-level script, below the previous one.
+    fn script(&mut self, stack: Vec<Block>) {
+        let position = (0, self.scripts as i64 * 600);
+        self.scripts += 1;
+        self.stack(stack, None, Some(position));
+    }
+
+    /// Add blocks stacked on each other, returning the id of the first.
+    fn stack(&mut self, stack: Vec<Block>, parent: Option<&str>, position: Option<(i64, i64)>) -> Option<String> {
+        let mut first = None;
+        let mut previous: Option<String> = None;
+        for block in stack {
+            let id = self.block(block, previous.as_deref().or(parent), position.filter(|_| first.is_none()));
+            if let Some(previous) = &previous {
+                self.blocks[previous]["next"] = json!(id);
+            }
+            first.get_or_insert_with(|| id.clone());
+            previous = Some(id);
+        }
+        first
+    }
+
+    fn block(&mut self, block: Block, parent: Option<&str>, position: Option<(i64, i64)>) -> String {
+        let id = self.id();
+
+        let mut inputs = Map::new();
+        for (name, input) in block.inputs {
+            let value = match input {
+                Input::Text(text) => json!([1, [10, text]]),
+                Input::Number(n) => json!([1, [4, n]]),
+                Input::Variable(var) => json!([3, [12, var.name, var.id], [10, ""]]),
+                Input::Reporter(reporter) => json!([3, self.block(*reporter, Some(&id), None), [10, ""]]),
+                Input::Condition(condition) => json!([2, self.block(*condition, Some(&id), None)]),
+                Input::Stack(stack) => {
+                    let Some(first) = self.stack(stack, Some(&id), None) else {
+                        continue;
+                    };
+                    json!([2, first])
+                }
+                Input::Shadow(shadow, cover) => {
+                    let shadow = self.block(*shadow, Some(&id), None);
+                    match cover.map(|cover| *cover) {
+                        Some(Input::Variable(var)) => json!([3, [12, var.name, var.id], shadow]),
+                        Some(Input::Reporter(reporter)) => json!([3, self.block(*reporter, Some(&id), None), shadow]),
+                        _ => json!([1, shadow]),
+                    }
+                }
+            };
+            inputs.insert(name, value);
+        }
+        let fields = block.fields
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect::<Map<_, _>>();
+
+        let mut json =
+            json!({
+            "opcode": block.opcode,
+            "next": null,
+            "parent": parent,
+            "inputs": inputs,
+            "fields": fields,
+            "shadow": block.shadow,
+            "topLevel": position.is_some(),
+        });
+        if let Some((x, y)) = position {
+            json["x"] = json!(x);
+            json["y"] = json!(y);
+        }
+        if let Some(mutation) = block.mutation {
+            json["mutation"] = mutation;
+        }
+        self.blocks.insert(id.clone(), json);
+        id
+    }
+}
+
+/// A zip archive with stored (uncompressed) files, which is all Scratch needs.
+fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+    // 1980-01-01, the earliest date a zip can hold.
+    const DATE: u16 = (1 << 5) | 1;
+
+    let mut out = Vec::new();
+    let mut central = Vec::new();
+    for (name, data) in files {
+        let offset = out.len() as u32;
+        let crc = crc32(data);
+        let size = data.len() as u32;
+
+        out.extend_from_slice(&0x04034b50_u32.to_le_bytes());
+        for n in [20_u16, 0, 0, 0, DATE] {
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+        for n in [crc, size, size] {
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+        out.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        out.extend_from_slice(&0_u16.to_le_bytes());
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(data);
+
+        central.extend_from_slice(&0x02014b50_u32.to_le_bytes());
+        for n in [20_u16, 20, 0, 0, 0, DATE] {
+            central.extend_from_slice(&n.to_le_bytes());
+        }
+        for n in [crc, size, size] {
+            central.extend_from_slice(&n.to_le_bytes());
+        }
+        for n in [name.len() as u16, 0, 0, 0, 0] {
+            central.extend_from_slice(&n.to_le_bytes());
+        }
+        for n in [0_u32, offset] {
+            central.extend_from_slice(&n.to_le_bytes());
+        }
+        central.extend_from_slice(name.as_bytes());
+    }
+
+    let central_offset = out.len() as u32;
+    out.extend_from_slice(&central);
+    out.extend_from_slice(&0x06054b50_u32.to_le_bytes());
+    for n in [0_u16, 0, files.len() as u16, files.len() as u16] {
+        out.extend_from_slice(&n.to_le_bytes());
+    }
+    for n in [central.len() as u32, central_offset] {
+        out.extend_from_slice(&n.to_le_bytes());
+    }
+    out.extend_from_slice(&0_u16.to_le_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0_u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::ScratchObject;
+
+    #[derive(ScratchObject)]
+    struct Player {
+        #[id(0)]
+        name: String,
+        #[id(1)]
+        score: u32,
+    }
+
+    #[derive(ScratchObject)]
+    enum Command {
+        #[id(0)]
+        Stop,
+        #[id(1)]
+        Say(String, u8),
+    }
+
+    #[derive(ScratchObject)]
+    #[scratch(version = 4)]
+    enum Versioned {
+        #[id(0)]
+        Stop,
+    }
+
+    #[derive(ScratchObject)]
+    #[scratch(compact)]
+    struct Note {
+        #[id(0)]
+        text: String,
+    }
+
+    /// The sorted names of the variables or lists of a sprite.
+    fn names(sprite: &Value, key: &str) -> Vec<String> {
+        let vars = sprite[key].as_object().unwrap().values();
+        let mut names = vars.map(|var| var[0].as_str().unwrap().to_string()).collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// The sorted custom blocks a sprite defines, checking on the way that its blocks link up.
+    fn procedures(sprite: &Value) -> Vec<String> {
+        let blocks = sprite["blocks"].as_object().unwrap();
+        for (id, block) in blocks {
+            if let Some(next) = block["next"].as_str() {
+                assert_eq!(blocks[next]["parent"], *id, "{id}");
+            }
+            match block["parent"].as_str() {
+                Some(parent) => assert!(blocks.contains_key(parent), "{id}"),
+                None => assert_eq!(block["topLevel"], true, "{id}"),
+            }
+        }
+
+        let mut procedures = blocks
+            .values()
+            .filter(|block| block["opcode"] == "procedures_prototype")
+            .map(|block| block["mutation"]["proccode"].as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        procedures.sort();
+        procedures
+    }
+
+    /// What the blocks setting variable `name` set it to: text, or the name of another variable.
+    fn set_to(sprite: &Value, name: &str) -> Vec<String> {
+        let blocks = sprite["blocks"].as_object().unwrap().values();
+        let mut values = blocks
+            .filter(|block| block["opcode"] == "data_setvariableto" && block["fields"]["VARIABLE"][0] == name)
+            .map(|block| block["inputs"]["VALUE"][1][1].as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        values.sort();
+        values
+    }
+
+    /// The top-level blocks of the definition of a custom block, in order: the opcode, with the variable
+    /// set or the argument passed (text, or the name of an argument).
+    fn script(sprite: &Value, proccode: &str) -> Vec<String> {
+        let blocks = sprite["blocks"].as_object().unwrap();
+        let prototype = blocks.values().find(|block| block["mutation"]["proccode"] == proccode).unwrap();
+        let mut block = &blocks[prototype["parent"].as_str().unwrap()];
+        let mut script = Vec::new();
+        while let Some(next) = block["next"].as_str() {
+            block = &blocks[next];
+            let detail = match block["opcode"].as_str().unwrap() {
+                "data_setvariableto" => block["fields"]["VARIABLE"][0].as_str().unwrap(),
+                "procedures_call" => match &block["inputs"].as_object().unwrap().values().next().unwrap()[1] {
+                    Value::String(reporter) => blocks[reporter]["fields"]["VALUE"][0].as_str().unwrap(),
+                    text => text[1].as_str().unwrap(),
+                },
+                _ => "",
+            };
+            script.push(format!("{} {detail}", block["opcode"].as_str().unwrap()).trim_end().to_string());
+        }
+        script
+    }
+
+    #[test]
+    fn struct_sprite() {
+        let sprite = Sprite::new("codec").with::<Player>().to_json();
+        assert_eq!(sprite["name"], "codec");
+        assert_eq!(sprite["costumes"].as_array().unwrap().len(), 27);
+
+        assert_eq!(procedures(&sprite), ["decode Player %s", "encode Player", "sb decode %s", "sb encode %s"]);
+        assert_eq!(names(&sprite, "lists"), ["sb items", "sb table"]);
+        let variables = names(&sprite, "variables");
+        assert_eq!(variables[..3], ["Player encoded", "Player.name", "Player.score"]);
+        assert_eq!(variables[3..], ["sb code", "sb encoded", "sb error", "sb i", "sb item", "sb k"]);
+    }
+
+    #[test]
+    fn unencodable_characters() {
+        // Emptied by `encode Player`, and set to characters missing from the table.
+        let sprite = Sprite::new("codec").with::<Player>().to_json();
+        assert_eq!(set_to(&sprite, "sb error"), ["", "sb item"]);
+
+        let blocks = sprite["blocks"].as_object().unwrap();
+        let error = blocks
+            .values()
+            .find(|block| block["fields"]["VARIABLE"][0] == "sb error" && block["inputs"]["VALUE"][0] == 3)
+            .unwrap();
+        let branch = &blocks[error["parent"].as_str().unwrap()];
+        assert_eq!(branch["opcode"], "control_if_else");
+        let condition = &blocks[branch["inputs"]["CONDITION"][1].as_str().unwrap()];
+        assert_eq!(condition["opcode"], "operator_equals");
+        assert_eq!(condition["inputs"]["OPERAND2"], json!([1, [10, "0"]]));
+
+        // `sb compact encode` also fails for characters of the table without a compact code.
+        let sprite = Sprite::new("codec").with::<Note>().to_json();
+        assert_eq!(set_to(&sprite, "sb error"), ["", "sb item", "sb item", "sb item"]);
+    }
+
+    #[test]
+    fn enum_sprite() {
+        let sprite = Sprite::new("codec").with::<Command>().sealed().to_json();
+        let expected = ["decode Command %s", "encode Command %s %s", "sb decode %s", "sb encode %s"];
+        assert_eq!(procedures(&sprite)[..4], expected);
+        assert_eq!(procedures(&sprite)[4..], ["sb seal %s", "sb verify %s"]);
+        let variables = names(&sprite, "variables");
+        for name in ["Command variant", "Command payload", "Command encoded", "sb valid", "sb verified"] {
+            assert!(variables.iter().any(|var| var == name), "{name}");
+        }
+    }
+
+    #[test]
+    fn enum_version() {
+        let sprite = Sprite::new("codec").with::<Versioned>().to_json();
+        let encode = [
+            "data_setvariableto sb encoded",
+            "data_setvariableto sb error",
+            "procedures_call 4",
+            "data_setvariableto sb encoded",
+            "procedures_call variant",
+            "data_setvariableto Versioned encoded",
+        ];
+        assert_eq!(script(&sprite, "encode Versioned %s %s"), encode);
+
+        // The version is read up to the first splitter and dropped, before the variant id.
+        let decode = script(&sprite, "decode Versioned %s");
+        let skipped = ["control_repeat_until", "data_setvariableto sb code"];
+        assert_eq!(decode[4..6], skipped);
+        assert_eq!(decode[6..], ["control_repeat_until", "control_repeat_until"]);
+        let unversioned = script(&Sprite::new("codec").with::<Command>().to_json(), "decode Command %s");
+        assert_eq!(unversioned[4..], ["control_repeat_until", "control_repeat_until"]);
+    }
+
+    #[test]
+    fn compact_sprite() {
+        let sprite = Sprite::new("codec").with::<Note>().to_json();
+        let procedures = procedures(&sprite);
+        assert!(procedures.iter().any(|proc| proc == "sb compact encode %s"), "{procedures:?}");
+        assert!(procedures.iter().any(|proc| proc == "sb compact decode %s"), "{procedures:?}");
+
+        let lists = sprite["lists"].as_object().unwrap().values().collect::<Vec<_>>();
+        let list = |name: &str| lists.iter().find(|list| list[0] == name).unwrap()[1].as_array().unwrap();
+        assert_eq!(list("sb compact codes").len(), list("sb table").len());
+        assert_eq!(list("sb compact").len(), CompactTable::TABLE.len());
+    }
+
+    #[test]
+    fn crc32_known_answers() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(crc32(b"The quick brown fox jumps over the lazy dog"), 0x414fa339);
+    }
+
+    #[test]
+    fn sprite3_is_a_zip() {
+        let sprite = Sprite::new("codec").with::<Player>();
+        let file = sprite.to_sprite3();
+        let u16_at = |at: usize| u16::from_le_bytes([file[at], file[at + 1]]) as usize;
+        let u32_at = |at: usize| u32::from_le_bytes(file[at..at + 4].try_into().unwrap()) as usize;
+
+        // The end of central directory record, which has no comment.
+        let end = file.len() - 22;
+        assert_eq!(u32_at(end), 0x06054b50);
+        let (count, size, offset) = (u16_at(end + 10), u32_at(end + 12), u32_at(end + 16));
+        assert_eq!(offset + size, end);
+
+        let mut files = Vec::new();
+        let mut at = offset;
+        for _ in 0..count {
+            assert_eq!(u32_at(at), 0x02014b50);
+            // Stored, not compressed.
+            assert_eq!(u16_at(at + 10), 0);
+            let name_length = u16_at(at + 28);
+            let name = String::from_utf8(file[at + 46..at + 46 + name_length].to_vec()).unwrap();
+
+            let local = u32_at(at + 42);
+            assert_eq!(u32_at(local), 0x04034b50);
+            let start = local + 30 + u16_at(local + 26) + u16_at(local + 28);
+            let data = file[start..start + u32_at(at + 24)].to_vec();
+            assert_eq!(crc32(&data) as usize, u32_at(at + 16));
+
+            files.push((name, data));
+            at += 46 + name_length + u16_at(at + 30) + u16_at(at + 32);
+        }
+        assert_eq!(at, end);
+
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].0, "sprite.json");
+        assert_eq!(serde_json::from_slice::<Value>(&files[0].1).unwrap(), sprite.to_json());
+        assert_eq!(files[1], (format!("{COSTUME_ID}.svg"), COSTUME_SVG.as_bytes().to_vec()));
+    }
+}
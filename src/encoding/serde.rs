@@ -0,0 +1,705 @@
+//! Encode any `serde` type, without deriving `ScratchObject`.
+//!
+//! Values are laid out like the [fields](super::field) of a `ScratchObject`: numbers, strings, booleans,
+//! chars and units are plain items, while structs, tuples, sequences, maps, options and enums are prefixed
+//! items, except at the top, where their items are written directly. Fields are read back by position, so a
+//! struct is encoded the same as a derived `ScratchObject` whose ids count up from `0`.
+//!
+//! - Sequences start with their length, maps with their number of entries, then each key and value.
+//! - Options are `0`, or `1` and the value.
+//! - Enum variants are their index, followed by the payload or the fields of the variant.
+//!
+//! ```
+//! use serde::{ Deserialize, Serialize };
+//! use scratchback::encoding::serde::{ from_str, to_string };
+//!
+//! #[derive(Debug, PartialEq, Serialize, Deserialize)]
+//! struct Player {
+//!     name: String,
+//!     scores: Vec<u32>,
+//! }
+//!
+//! let player = Player { name: "Al".to_string(), scores: vec![3, 7] };
+//! let encoded = to_string(&player).unwrap();
+//! assert_eq!(from_str::<Player>(&encoded).unwrap(), player);
+//! ```
+//!
+//! Since fields have no names in the encoding, `#[serde(skip_serializing_if = "...")]` and `#[serde(flatten)]`
+//! cannot be read back.
+
+use ::serde::{ de, ser, Deserialize, Serialize };
+
+use super::{ Encoding, EncodingError, ItemReader, ItemWriter, SbStringTo, SbToString };
+
+type Result<T> = core::result::Result<T, EncodingError>;
+
+/// Encode `value` with [`Encoding`].
+pub fn to_string<T: Serialize + ?Sized>(value: &T) -> Result<String> {
+    let mut items = ItemWriter::new(false);
+    value.serialize(Serializer { items: &mut items, top: true })?;
+    Encoding::try_encode(&items.finish())
+}
+
+/// Decode a value encoded by [`to_string`].
+pub fn from_str<T: de::DeserializeOwned>(numbers: &str) -> Result<T> {
+    let text = Encoding::try_decode(numbers)?;
+    let mut items = ItemReader::new(&text, false);
+    let value = T::deserialize(Deserializer { items: &mut items, top: true })?;
+    items.finish()?;
+    Ok(value)
+}
+
+impl ser::Error for EncodingError {
+    fn custom<T: core::fmt::Display>(msg: T) -> Self {
+        Self::Custom(msg.to_string())
+    }
+}
+
+impl de::Error for EncodingError {
+    fn custom<T: core::fmt::Display>(msg: T) -> Self {
+        Self::Custom(msg.to_string())
+    }
+}
+
+struct Serializer<'a> {
+    items: &'a mut ItemWriter,
+    /// Whether this is the outermost value, whose items are not prefixed.
+    top: bool,
+}
+
+impl<'a> Serializer<'a> {
+    fn compound(self, length: Length, variant: Option<u32>) -> Compound<'a> {
+        let mut items = ItemWriter::new(false);
+        if let Some(variant) = variant {
+            // Indices are digits only, which are always a valid item.
+            let _ = items.item(itoa::Buffer::new().format(variant));
+        }
+        Compound { parent: self.items, items, length, written: 0, top: self.top }
+    }
+}
+
+/// What a compound value starts with.
+#[derive(Clone, Copy)]
+enum Length {
+    None,
+    Elements,
+    Entries,
+}
+
+struct Compound<'a> {
+    parent: &'a mut ItemWriter,
+    items: ItemWriter,
+    length: Length,
+    written: usize,
+    top: bool,
+}
+
+impl Compound<'_> {
+    /// Write the next element, or the field `name` of a struct.
+    fn element<T: Serialize + ?Sized>(&mut self, value: &T, name: Option<&'static str>) -> Result<()> {
+        let index = self.written;
+        let offset = self.items.offset();
+        self.written += 1;
+
+        value.serialize(Serializer { items: &mut self.items, top: false }).map_err(|err| match name {
+            Some(name) => err.in_field(index as u8, name, offset),
+            None => err.in_element(index, offset),
+        })
+    }
+
+    fn end(self) -> Result<()> {
+        let length = match self.length {
+            Length::None => None,
+            Length::Elements => Some(self.written),
+            Length::Entries => Some(self.written / 2),
+        };
+        let items = match length {
+            Some(length) => {
+                let mut counted = ItemWriter::new(false);
+                counted.item(itoa::Buffer::new().format(length))?;
+                counted.extend(self.items);
+                counted
+            }
+            None => self.items,
+        };
+
+        match self.top {
+            true => {
+                self.parent.extend(items);
+                Ok(())
+            }
+            false => self.parent.prefixed(&items.finish()),
+        }
+    }
+}
+
+macro_rules! serialize_plain {
+    ($($method:ident($typ:ty)),*) => {
+        $(
+            fn $method(self, v: $typ) -> Result<()> {
+                self.items.item(&v.sb_to_string())
+            }
+        )*
+    };
+}
+
+impl<'a> ser::Serializer for Serializer<'a> {
+    type Ok = ();
+    type Error = EncodingError;
+    type SerializeSeq = Compound<'a>;
+    type SerializeTuple = Compound<'a>;
+    type SerializeTupleStruct = Compound<'a>;
+    type SerializeTupleVariant = Compound<'a>;
+    type SerializeMap = Compound<'a>;
+    type SerializeStruct = Compound<'a>;
+    type SerializeStructVariant = Compound<'a>;
+
+    serialize_plain!(
+        serialize_bool(bool), serialize_char(char),
+        serialize_i8(i8), serialize_i16(i16), serialize_i32(i32), serialize_i64(i64), serialize_i128(i128),
+        serialize_u8(u8), serialize_u16(u16), serialize_u32(u32), serialize_u64(u64), serialize_u128(u128),
+        serialize_f32(f32), serialize_f64(f64)
+    );
+
+    fn serialize_str(self, v: &str) -> Result<()> {
+        self.items.item(v)
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> {
+        let mut seq = self.compound(Length::Elements, None);
+        for byte in v {
+            seq.element(byte, None)?;
+        }
+        seq.end()
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        let mut option = self.compound(Length::None, None);
+        option.items.item("0")?;
+        option.end()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<()> {
+        let mut option = self.compound(Length::None, None);
+        option.items.item("1")?;
+        option.element(value, None)?;
+        option.end()
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        self.items.item("")
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, variant_index: u32, _variant: &'static str) -> Result<()> {
+        self.compound(Length::None, Some(variant_index)).end()
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        value: &T
+    ) -> Result<()> {
+        let mut variant = self.compound(Length::None, Some(variant_index));
+        variant.element(value, None)?;
+        variant.end()
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Compound<'a>> {
+        Ok(self.compound(Length::Elements, None))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Compound<'a>> {
+        Ok(self.compound(Length::None, None))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Compound<'a>> {
+        Ok(self.compound(Length::None, None))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize
+    ) -> Result<Compound<'a>> {
+        Ok(self.compound(Length::None, Some(variant_index)))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Compound<'a>> {
+        Ok(self.compound(Length::Entries, None))
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Compound<'a>> {
+        Ok(self.compound(Length::None, None))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize
+    ) -> Result<Compound<'a>> {
+        Ok(self.compound(Length::None, Some(variant_index)))
+    }
+}
+
+macro_rules! serialize_compound {
+    ($($trait:ident::$method:ident),*) => {
+        $(
+            impl ser::$trait for Compound<'_> {
+                type Ok = ();
+                type Error = EncodingError;
+
+                fn $method<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+                    self.element(value, None)
+                }
+
+                fn end(self) -> Result<()> {
+                    Compound::end(self)
+                }
+            }
+        )*
+    };
+}
+
+serialize_compound!(
+    SerializeSeq::serialize_element,
+    SerializeTuple::serialize_element,
+    SerializeTupleStruct::serialize_field,
+    SerializeTupleVariant::serialize_field
+);
+
+impl ser::SerializeMap for Compound<'_> {
+    type Ok = ();
+    type Error = EncodingError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        self.element(key, None)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value, None)
+    }
+
+    fn end(self) -> Result<()> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeStruct for Compound<'_> {
+    type Ok = ();
+    type Error = EncodingError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()> {
+        self.element(value, Some(key))
+    }
+
+    fn end(self) -> Result<()> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeStructVariant for Compound<'_> {
+    type Ok = ();
+    type Error = EncodingError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()> {
+        self.element(value, Some(key))
+    }
+
+    fn end(self) -> Result<()> {
+        Compound::end(self)
+    }
+}
+
+struct Deserializer<'a, 'de> {
+    items: &'a mut ItemReader<'de>,
+    /// Whether this is the outermost value, whose items are not prefixed.
+    top: bool,
+}
+
+impl<'de> Deserializer<'_, 'de> {
+    fn parse<T>(self, expected: &'static str) -> Result<T> where String: SbStringTo<T> {
+        let item = self.items.item()?.to_string();
+        match SbStringTo::<T>::sb_string_to(&item) {
+            Some(value) => Ok(value),
+            None => Err(EncodingError::Parse { expected, value: item }),
+        }
+    }
+
+    /// Read the items of a compound value with `read`.
+    fn compound<T>(self, read: impl FnOnce(&mut ItemReader<'de>) -> Result<T>) -> Result<T> {
+        if self.top {
+            return read(self.items);
+        }
+
+        let mut nested = self.items.prefixed()?;
+        let value = read(&mut nested)?;
+        nested.finish()?;
+        Ok(value)
+    }
+}
+
+fn length(items: &mut ItemReader<'_>) -> Result<usize> {
+    let length = items.item()?;
+    atoi::atoi::<usize>(length.as_bytes()).ok_or_else(|| EncodingError::Parse {
+        expected: "a length",
+        value: length.to_string(),
+    })
+}
+
+macro_rules! deserialize_plain {
+    ($($method:ident => $visit:ident($typ:ty)),*) => {
+        $(
+            fn $method<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+                visitor.$visit(self.parse::<$typ>(stringify!($typ))?)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer<'_, 'de> {
+    type Error = EncodingError;
+
+    fn deserialize_any<V: de::Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        Err(EncodingError::Custom("the encoding does not describe its types, so they must be known".to_string()))
+    }
+
+    deserialize_plain!(
+        deserialize_bool => visit_bool(bool), deserialize_char => visit_char(char),
+        deserialize_i8 => visit_i8(i8), deserialize_i16 => visit_i16(i16), deserialize_i32 => visit_i32(i32),
+        deserialize_i64 => visit_i64(i64), deserialize_i128 => visit_i128(i128),
+        deserialize_u8 => visit_u8(u8), deserialize_u16 => visit_u16(u16), deserialize_u32 => visit_u32(u32),
+        deserialize_u64 => visit_u64(u64), deserialize_u128 => visit_u128(u128),
+        deserialize_f32 => visit_f32(f32), deserialize_f64 => visit_f64(f64)
+    );
+
+    fn deserialize_str<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_str(self.items.item()?)
+    }
+
+    fn deserialize_string<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let bytes = self.compound(|items| {
+            let length = length(items)?;
+            (0..length)
+                .map(|index| {
+                    let offset = items.position();
+                    u8::deserialize(Deserializer { items: &mut *items, top: false })
+                        .map_err(|err| err.in_element(index, offset))
+                })
+                .collect::<Result<Vec<u8>>>()
+        })?;
+        visitor.visit_byte_buf(bytes)
+    }
+
+    fn deserialize_byte_buf<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.compound(|items| match items.item()? {
+            "0" => visitor.visit_none(),
+            "1" => {
+                let offset = items.position();
+                visitor.visit_some(Deserializer { items, top: false }).map_err(|err| err.in_element(0, offset))
+            }
+            flag => Err(EncodingError::Parse { expected: "0 or 1", value: flag.to_string() }),
+        })
+    }
+
+    fn deserialize_unit<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.items.item()? {
+            "" => visitor.visit_unit(),
+            item => Err(EncodingError::Parse { expected: "an empty item", value: item.to_string() }),
+        }
+    }
+
+    fn deserialize_unit_struct<V: de::Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V
+    ) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.compound(|items| {
+            let length = length(items)?;
+            visitor.visit_seq(Access { items, length, index: 0, fields: None })
+        })
+    }
+
+    fn deserialize_tuple<V: de::Visitor<'de>>(self, length: usize, visitor: V) -> Result<V::Value> {
+        self.compound(|items| visitor.visit_seq(Access { items, length, index: 0, fields: None }))
+    }
+
+    fn deserialize_tuple_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        length: usize,
+        visitor: V
+    ) -> Result<V::Value> {
+        self.deserialize_tuple(length, visitor)
+    }
+
+    fn deserialize_map<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.compound(|items| {
+            let length = length(items)?;
+            visitor.visit_map(Access { items, length, index: 0, fields: None })
+        })
+    }
+
+    fn deserialize_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V
+    ) -> Result<V::Value> {
+        self.compound(|items| visitor.visit_seq(Access { items, length: fields.len(), index: 0, fields: Some(fields) }))
+    }
+
+    fn deserialize_enum<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V
+    ) -> Result<V::Value> {
+        self.compound(|items| {
+            let variant = Deserializer { items: &mut *items, top: false }.parse::<u32>("a variant index")?;
+            visitor.visit_enum(Variant { items, variant })
+        })
+    }
+
+    fn deserialize_identifier<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_ignored_any<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_any(visitor)
+    }
+}
+
+/// The elements of a sequence, tuple or map, or the fields of a struct.
+struct Access<'a, 'de> {
+    items: &'a mut ItemReader<'de>,
+    /// How many elements or entries there are.
+    length: usize,
+    index: usize,
+    fields: Option<&'static [&'static str]>,
+}
+
+impl<'de> Access<'_, 'de> {
+    fn element<T: de::DeserializeSeed<'de>>(&mut self, seed: T) -> Result<T::Value> {
+        let offset = self.items.position();
+        let value = seed.deserialize(Deserializer { items: &mut *self.items, top: false });
+        value.map_err(|err| match self.fields {
+            Some(fields) => err.in_field(self.index as u8, fields[self.index], offset),
+            None => err.in_element(self.index, offset),
+        })
+    }
+}
+
+impl<'de> de::SeqAccess<'de> for Access<'_, 'de> {
+    type Error = EncodingError;
+
+    fn next_element_seed<T: de::DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        if self.index == self.length {
+            return Ok(None);
+        }
+        let value = self.element(seed)?;
+        self.index += 1;
+        Ok(Some(value))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.length - self.index)
+    }
+}
+
+impl<'de> de::MapAccess<'de> for Access<'_, 'de> {
+    type Error = EncodingError;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        if self.index == self.length {
+            return Ok(None);
+        }
+        self.element(seed).map(Some)
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        let value = self.element(seed)?;
+        self.index += 1;
+        Ok(value)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.length - self.index)
+    }
+}
+
+struct Variant<'a, 'de> {
+    items: &'a mut ItemReader<'de>,
+    variant: u32,
+}
+
+impl<'a, 'de> de::EnumAccess<'de> for Variant<'a, 'de> {
+    type Error = EncodingError;
+    type Variant = Self;
+
+    fn variant_seed<T: de::DeserializeSeed<'de>>(self, seed: T) -> Result<(T::Value, Self)> {
+        let variant = seed.deserialize(de::value::U32Deserializer::<EncodingError>::new(self.variant))?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Variant<'_, 'de> {
+    type Error = EncodingError;
+
+    fn unit_variant(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        let mut access = Access { items: self.items, length: 1, index: 0, fields: None };
+        access.element(seed)
+    }
+
+    fn tuple_variant<V: de::Visitor<'de>>(self, length: usize, visitor: V) -> Result<V::Value> {
+        visitor.visit_seq(Access { items: self.items, length, index: 0, fields: None })
+    }
+
+    fn struct_variant<V: de::Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V
+    ) -> Result<V::Value> {
+        visitor.visit_seq(Access { items: self.items, length: fields.len(), index: 0, fields: Some(fields) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use ::serde::{ de::DeserializeOwned, Deserialize, Serialize };
+
+    // Not `super::*`: its `Result` would shadow the one the derive uses.
+    use super::{ from_str, to_string };
+    use crate::encoding::{ Encoding, EncodingError, ScratchObject };
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Player {
+        name: String,
+        scores: Vec<u32>,
+    }
+
+    #[derive(ScratchObject, Debug, PartialEq)]
+    struct DerivedPlayer {
+        #[id(0)]
+        name: String,
+        #[id(1)]
+        scores: Vec<u32>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    enum Shape {
+        Dot,
+        Circle(u32),
+        Rect(u32, u32),
+        Named {
+            name: String,
+            sides: u8,
+        },
+    }
+
+    fn text<T: Serialize>(value: &T) -> String {
+        Encoding::decode(&to_string(value).unwrap()).unwrap()
+    }
+
+    fn round_trip<T: Serialize + DeserializeOwned + PartialEq + core::fmt::Debug>(value: T) {
+        assert_eq!(from_str::<T>(&to_string(&value).unwrap()).unwrap(), value);
+    }
+
+    #[test]
+    fn structs_match_the_derive() {
+        let player = Player { name: "Al".to_string(), scores: vec![3, 7] };
+        let derived = DerivedPlayer { name: "Al".to_string(), scores: vec![3, 7] };
+
+        let numbers = to_string(&player).unwrap();
+        assert_eq!(Encoding::decode(&numbers).unwrap(), "Al•5:2•3•7");
+        assert_eq!(numbers, derived.try_sb_encode().unwrap());
+        assert_eq!(DerivedPlayer::try_from_sb_encoded(&numbers).unwrap().scores, [3, 7]);
+        round_trip(player);
+    }
+
+    #[test]
+    fn enums() {
+        assert_eq!(text(&Shape::Dot), "0");
+        assert_eq!(text(&Shape::Rect(2, 3)), "2•2•3");
+        let named = Shape::Named { name: "tri".to_string(), sides: 3 };
+        assert_eq!(text(&vec![Shape::Circle(4), named.clone()]), "2•3:1•4•7:3•tri•3");
+
+        for shape in [Shape::Dot, Shape::Circle(4), Shape::Rect(2, 3), named] {
+            round_trip(vec![shape.clone(), shape]);
+        }
+    }
+
+    #[test]
+    fn options_maps_and_tuples() {
+        let mut map = BTreeMap::new();
+        map.insert("a".to_string(), Some(('x', true)));
+        map.insert(String::new(), None);
+        assert_eq!(text(&map), "2••1:0•a•7:1•3:x•1");
+        round_trip(map);
+
+        round_trip((Some(Some(1_u8)), Some(None::<u8>), (), [-1_i64, 0, 1]));
+        round_trip(Vec::<Vec<String>>::from([vec![], vec![String::new()], vec![" ".to_string()]]));
+    }
+
+    #[test]
+    fn errors_name_the_field() {
+        let err = to_string(&Shape::Named { name: "a•b".to_string(), sides: 3 }).unwrap_err();
+        // Bytes of "3•".
+        let source = Box::new(EncodingError::Unencodable { offset: 1, chr: '•' });
+        assert_eq!(err, EncodingError::Field { id: 0, name: "name", offset: 4, source });
+
+        let err = from_str::<Player>(&Encoding::encode("Al•3:1•x").unwrap()).unwrap_err();
+        // "Al•", then "3:1•" of the field.
+        let source = EncodingError::Parse { expected: "u32", value: "x".to_string() }.in_element(0, 8);
+        assert_eq!(err, EncodingError::Field { id: 1, name: "scores", offset: 6, source: Box::new(source) });
+        assert_eq!(err.offset(), Some(14));
+
+        let err = from_str::<Player>(&Encoding::encode("Al•3:0•x").unwrap()).unwrap_err();
+        let source = Box::new(EncodingError::TrailingItems { offset: 8 });
+        assert_eq!(err, EncodingError::Field { id: 1, name: "scores", offset: 6, source });
+
+        let err = from_str::<Player>(&Encoding::encode("Al•1:0•x").unwrap()).unwrap_err();
+        assert_eq!(err, EncodingError::TrailingItems { offset: 14 });
+
+        let err = from_str::<Vec<Shape>>(&Encoding::encode("1•1:7").unwrap()).unwrap_err();
+        let EncodingError::Element { index: 0, offset: 4, source } = &err else {
+            panic!("{err:?}");
+        };
+        assert!(matches!(&**source, EncodingError::Custom(_)), "{err:?}");
+    }
+}
@@ -0,0 +1,363 @@
+//! Encoding and decoding without building a `String` per character or item.
+//!
+//! Every encoding can write its digits into a reused `String`, any `fmt::Write` or an `io::Write`, and read
+//! values back through borrowing iterators:
+//!
+//! ```
+//! use scratchback::encoding::Encoding;
+//!
+//! let mut numbers = String::new();
+//! Encoding::try_encode_into("Walter•42", &mut numbers).unwrap();
+//!
+//! let mut name = String::new();
+//! let mut items = Encoding::items(&numbers);
+//! items.next().unwrap().unwrap().decode_into(&mut name).unwrap();
+//! assert_eq!(name, "Walter");
+//!
+//! let age = items.next().unwrap().unwrap();
+//! assert_eq!(age.digits(), "0503");
+//! assert!(age.chars().eq([Ok('4'), Ok('2')]));
+//! ```
+
+use core::{ fmt, iter::FusedIterator, marker::PhantomData };
+use std::io;
+
+use super::{ decode_char, encode_to, find_code, Codec, EncodingError, EncodingTable, Table };
+
+/// The characters of a value, decoded one code of table `T` at a time.
+///
+/// Offsets of errors count from the start of the whole value. Nothing is yielded after an error.
+#[derive(Debug, Clone)]
+pub struct Chars<'a, T: Table = EncodingTable> {
+    numbers: &'a str,
+    pos: usize,
+    end: usize,
+    codec: Codec,
+    /// Found before decoding anything, yielded first.
+    error: Option<EncodingError>,
+    table: PhantomData<T>,
+}
+
+impl<'a, T: Table> Chars<'a, T> {
+    pub(crate) fn new(numbers: &'a str, codec: Codec) -> Self {
+        Self { numbers, pos: 0, end: numbers.len(), codec, error: odd_length(numbers, codec), table: PhantomData }
+    }
+
+    /// The digits that have not been decoded yet.
+    pub fn remaining(&self) -> &'a str {
+        &self.numbers[self.pos..self.end]
+    }
+}
+
+impl<T: Table> Iterator for Chars<'_, T> {
+    type Item = Result<char, EncodingError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(err) = self.error.take() {
+            self.pos = self.end;
+            return Some(Err(err));
+        }
+        if self.pos >= self.end {
+            return None;
+        }
+
+        match decode_char::<T>(self.numbers, self.pos, self.end, self.codec) {
+            Ok((chr, width)) => {
+                self.pos += width;
+                Some(Ok(chr))
+            }
+            Err(err) => {
+                self.pos = self.end;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+impl<T: Table> FusedIterator for Chars<'_, T> {}
+
+/// The items of a value, split at every splitter without decoding them.
+///
+/// An empty value has no items and, unlike in `try_decode_items`, a trailing splitter ends an empty one. An
+/// odd number of digits is the only error, yielded instead of any item.
+#[derive(Debug, Clone)]
+pub struct Items<'a, T: Table = EncodingTable> {
+    numbers: &'a str,
+    pos: usize,
+    codec: Codec,
+    splitter: &'static str,
+    done: bool,
+    error: Option<EncodingError>,
+    table: PhantomData<T>,
+}
+
+impl<'a, T: Table> Items<'a, T> {
+    pub(crate) fn new(numbers: &'a str, codec: Codec, splitter: &'static str) -> Self {
+        let error = odd_length(numbers, codec);
+        Self { numbers, pos: 0, codec, splitter, done: numbers.is_empty(), error, table: PhantomData }
+    }
+}
+
+impl<'a, T: Table> Iterator for Items<'a, T> {
+    type Item = Result<Item<'a, T>, EncodingError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(err) = self.error.take() {
+            self.done = true;
+            return Some(Err(err));
+        }
+        if self.done {
+            return None;
+        }
+
+        let start = self.pos;
+        let end = match find_code(self.numbers, start, self.splitter, self.codec) {
+            Some(splitter) => {
+                self.pos = splitter + self.splitter.len();
+                splitter
+            }
+            None => {
+                self.done = true;
+                self.numbers.len()
+            }
+        };
+        Some(Ok(Item { numbers: self.numbers, start, end, codec: self.codec, table: PhantomData }))
+    }
+}
+
+impl<T: Table> FusedIterator for Items<'_, T> {}
+
+/// One item of a value, still encoded.
+#[derive(Debug, Clone, Copy)]
+pub struct Item<'a, T: Table = EncodingTable> {
+    numbers: &'a str,
+    start: usize,
+    end: usize,
+    codec: Codec,
+    table: PhantomData<T>,
+}
+
+impl<'a, T: Table> Item<'a, T> {
+    /// The digits of this item, without the splitter.
+    pub fn digits(&self) -> &'a str {
+        &self.numbers[self.start..self.end]
+    }
+
+    /// Where this item starts in the digits of the whole value.
+    pub fn offset(&self) -> usize {
+        self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn chars(&self) -> Chars<'a, T> {
+        Chars { numbers: self.numbers, pos: self.start, end: self.end, codec: self.codec, error: None, table: PhantomData }
+    }
+
+    /// Append the decoded item to `out`, which can be reused between items. On error, `out` is left as it was.
+    pub fn decode_into(&self, out: &mut String) -> Result<(), EncodingError> {
+        let len = out.len();
+        for chr in self.chars() {
+            match chr {
+                Ok(chr) => out.push(chr),
+                Err(err) => {
+                    out.truncate(len);
+                    return Err(err);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Two-digit codes cannot add up to an odd number of digits.
+fn odd_length(numbers: &str, codec: Codec) -> Option<EncodingError> {
+    match codec {
+        Codec::Compact => None,
+        _ if numbers.len().is_multiple_of(2) => None,
+        _ => Some(EncodingError::OddLength { length: numbers.len() }),
+    }
+}
+
+/// Encode into an `io::Write` through a small buffer, so it is not written to one digit at a time.
+pub(crate) fn encode_io<T: Table, W: io::Write + ?Sized>(input: &str, codec: Codec, out: &mut W) -> io::Result<()> {
+    struct Buffered<'a, W: ?Sized> {
+        out: &'a mut W,
+        buf: [u8; 256],
+        len: usize,
+        error: Option<io::Error>,
+    }
+
+    impl<W: io::Write + ?Sized> Buffered<'_, W> {
+        fn flush(&mut self) -> io::Result<()> {
+            let len = core::mem::take(&mut self.len);
+            self.out.write_all(&self.buf[..len])
+        }
+    }
+
+    impl<W: io::Write + ?Sized> fmt::Write for Buffered<'_, W> {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            if self.len + s.len() > self.buf.len() {
+                self.flush().map_err(|err| {
+                    self.error = Some(err);
+                    fmt::Error
+                })?;
+            }
+            if s.len() > self.buf.len() {
+                return self.out.write_all(s.as_bytes()).map_err(|err| {
+                    self.error = Some(err);
+                    fmt::Error
+                });
+            }
+            self.buf[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+            self.len += s.len();
+            Ok(())
+        }
+    }
+
+    let mut buffered = Buffered { out, buf: [0; 256], len: 0, error: None };
+    match encode_to::<T, _>(input, codec, &mut buffered) {
+        Ok(()) => buffered.flush(),
+        Err(EncodingError::Write) => Err(buffered.error.take().unwrap_or_else(|| io::Error::other(EncodingError::Write))),
+        Err(err) => {
+            // Still write the digits before the bad character, as `try_encode_fmt` does.
+            buffered.flush()?;
+            Err(io::Error::new(io::ErrorKind::InvalidInput, err))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::{ CompactEncoding, Encoding, ExtendedEncoding };
+
+    fn decode(item: Item<'_>) -> String {
+        let mut out = String::new();
+        item.decode_into(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn items() {
+        let numbers = Encoding::encode("Al•42••x").unwrap();
+        let items = Encoding::items(&numbers).map(Result::unwrap).collect::<Vec<_>>();
+
+        assert_eq!(items.iter().map(Item::digits).collect::<Vec<_>>(), ["3722", "0503", "", "34"]);
+        assert_eq!(items.iter().map(Item::offset).collect::<Vec<_>>(), [0, 6, 12, 14]);
+        assert_eq!(items.iter().map(Item::is_empty).collect::<Vec<_>>(), [false, false, true, false]);
+        let decoded = Encoding::try_decode_items(&numbers).unwrap();
+        assert_eq!(items.into_iter().map(decode).collect::<Vec<_>>(), decoded);
+
+        for (index, item) in Encoding::items(&numbers).enumerate() {
+            assert_eq!(item.unwrap().offset(), Encoding::item_offset(&decoded, index));
+        }
+    }
+
+    #[test]
+    fn items_at_the_edges() {
+        assert_eq!(Encoding::items("").count(), 0);
+
+        let items = Encoding::items("97").map(|item| item.unwrap().offset()).collect::<Vec<_>>();
+        assert_eq!(items, [0, 2]);
+
+        let mut items = Encoding::items("123");
+        assert_eq!(items.next().unwrap().unwrap_err(), EncodingError::OddLength { length: 3 });
+        assert!(items.next().is_none());
+        assert!(items.next().is_none());
+
+        // Compact codes have no even length, and a `97` inside a code is not a splitter.
+        let numbers = CompactEncoding::encode("a•b").unwrap();
+        assert_eq!(CompactEncoding::items(&numbers).count(), 2);
+        assert_eq!(Encoding::items("1097").map(|item| item.unwrap().digits()).collect::<Vec<_>>(), ["10", ""]);
+        assert_eq!(Encoding::items("0970").count(), 1);
+    }
+
+    #[test]
+    fn extended_escapes() {
+        let text = "é•😀\u{10FFFF}a";
+        let numbers = ExtendedEncoding::try_encode(text).unwrap();
+        assert_eq!(numbers, "980002339798128512990111411111");
+        assert_eq!(ExtendedEncoding::try_decode(&numbers).unwrap(), text);
+
+        let items = ExtendedEncoding::items(&numbers).map(Result::unwrap).collect::<Vec<_>>();
+        assert_eq!(items.iter().map(Item::offset).collect::<Vec<_>>(), [0, 10]);
+        assert!(items[1].chars().eq([Ok('😀'), Ok('\u{10FFFF}'), Ok('a')]));
+
+        // Plain `Encoding` has no escapes.
+        let err = Encoding::try_decode("98000233").unwrap_err();
+        assert_eq!(err, EncodingError::InvalidCode { offset: 0, pair: "98".to_string() });
+    }
+
+    #[test]
+    fn invalid_escapes() {
+        // Too large, a surrogate, and cut off; offsets count from the start of the whole value.
+        for (escape, offset) in [("9999999999", 4), ("98055296", 4), ("980002", 4)] {
+            let numbers = format!("1197{escape}");
+            let mut items = ExtendedEncoding::items(&numbers).map(Result::unwrap);
+            assert_eq!(decode(items.next().unwrap()), "a");
+
+            let mut chars = items.next().unwrap().chars();
+            let expected = EncodingError::InvalidEscape { offset, escape: escape.to_string() };
+            assert_eq!(chars.next(), Some(Err(expected)), "{escape}");
+            assert_eq!(chars.next(), None);
+        }
+    }
+
+    #[test]
+    fn chars() {
+        let numbers = Encoding::encode("abc").unwrap();
+        let mut chars = Encoding::chars(&numbers);
+        assert_eq!(chars.next(), Some(Ok('a')));
+        assert_eq!(chars.remaining(), "1213");
+
+        let mut chars = Encoding::chars("11991");
+        assert_eq!(chars.next(), Some(Err(EncodingError::OddLength { length: 5 })));
+        assert_eq!(chars.next(), None);
+
+        let mut chars = Encoding::chars("1199");
+        assert_eq!(chars.next(), Some(Ok('a')));
+        assert_eq!(chars.next(), Some(Err(EncodingError::InvalidCode { offset: 2, pair: "99".to_string() })));
+        assert_eq!(chars.next(), None);
+    }
+
+    #[test]
+    fn decode_into_reuses_the_buffer() {
+        let numbers = Encoding::encode("ab•cd").unwrap();
+        let mut out = String::from(">");
+        for item in Encoding::items(&numbers) {
+            item.unwrap().decode_into(&mut out).unwrap();
+        }
+        assert_eq!(out, ">abcd");
+
+        let item = Encoding::items("1199").next().unwrap().unwrap();
+        assert!(item.decode_into(&mut out).is_err());
+        assert_eq!(out, ">abcd");
+    }
+
+    #[test]
+    fn encode_into_writers() {
+        let mut out = String::from("00");
+        Encoding::try_encode_into("a", &mut out).unwrap();
+        assert!(Encoding::try_encode_into("bé", &mut out).is_err());
+        assert_eq!(out, "0011");
+
+        let mut written = String::new();
+        let err = Encoding::try_encode_fmt("bé", &mut written).unwrap_err();
+        assert_eq!(err, EncodingError::Unencodable { offset: 1, chr: 'é' });
+        assert_eq!(written, "12");
+
+        // Longer than the buffer of `encode_io`.
+        let text = "a".repeat(300);
+        let mut bytes = Vec::new();
+        Encoding::try_encode_io(&text, &mut bytes).unwrap();
+        assert_eq!(String::from_utf8(bytes).unwrap(), Encoding::encode(&text).unwrap());
+
+        let mut bytes = Vec::new();
+        let err = Encoding::try_encode_io("bé", &mut bytes).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(bytes, b"12");
+    }
+}
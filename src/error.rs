@@ -0,0 +1,263 @@
+//! A crate-wide error type.
+//!
+//! Every module keeps its own error enum describing exactly what went wrong, and each of them converts into
+//! [`Error`], which sorts failures into an [`ErrorKind`] so callers can decide how to react:
+//!
+//! ```no_run
+//! # #[cfg(feature = "cloud")]
+//! # async fn run() {
+//! use scratchback::{ cloud::Cloud, Error };
+//!
+//! loop {
+//!     match Cloud::connect("bot".to_string()).await {
+//!         Ok(_cloud) => break,
+//!         Err(err) if err.is_retryable() => continue,
+//!         Err(err) => panic!("{err}"),
+//!     }
+//! }
+//! # }
+//! ```
+
+use core::fmt;
+
+type Source = Box<dyn core::error::Error + Send + Sync>;
+
+/// What kind of failure an [`Error`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The connection could not be made or broke, such as DNS, TCP or TLS failures.
+    Network,
+    /// The other side sent (or we would send) something that does not follow the protocol.
+    Protocol,
+    /// The session was rejected.
+    Auth,
+    /// A value could not be encoded or decoded.
+    Encoding,
+    /// Too many requests; the server asked us to slow down.
+    RateLimited,
+    /// Waited too long for an answer.
+    Timeout,
+    /// The connection was closed, or the task owning it stopped.
+    Closed,
+    /// Messages arrived faster than they were read, and some were dropped.
+    Lagged,
+}
+
+impl ErrorKind {
+    fn describe(self) -> &'static str {
+        match self {
+            Self::Network => "network error",
+            Self::Protocol => "protocol error",
+            Self::Auth => "authentication failed",
+            Self::Encoding => "encoding error",
+            Self::RateLimited => "rate limited",
+            Self::Timeout => "timed out",
+            Self::Closed => "connection closed",
+            Self::Lagged => "messages dropped",
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.describe())
+    }
+}
+
+/// Any error of this crate, see the [module docs](self).
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    source: Option<Source>,
+}
+
+impl Error {
+    pub fn new<E: Into<Source>>(kind: ErrorKind, source: E) -> Self {
+        Self { kind, source: Some(source.into()) }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Whether trying the same thing again later may succeed.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self.kind, ErrorKind::Network | ErrorKind::RateLimited | ErrorKind::Timeout)
+    }
+
+    /// The error this one was made from.
+    pub fn get_ref(&self) -> Option<&(dyn core::error::Error + Send + Sync + 'static)> {
+        self.source.as_deref()
+    }
+
+    /// The error this one was made from, downcast to a concrete type.
+    pub fn downcast_ref<E: core::error::Error + 'static>(&self) -> Option<&E> {
+        self.source.as_deref()?.downcast_ref()
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Self { kind, source: None }
+    }
+}
+
+/// Only the kind; the details are in [`source`](core::error::Error::source).
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)
+    }
+}
+
+impl core::error::Error for Error {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        self.source.as_deref().map(|source| source as &(dyn core::error::Error + 'static))
+    }
+}
+
+/// The kind of an HTTP status, for handshakes and requests that were answered with one.
+fn status_kind(status: u16) -> ErrorKind {
+    match status {
+        401 | 403 => ErrorKind::Auth,
+        429 => ErrorKind::RateLimited,
+        408 | 504 => ErrorKind::Timeout,
+        500..=599 => ErrorKind::Network,
+        _ => ErrorKind::Protocol,
+    }
+}
+
+fn reqwest_kind(err: &reqwest::Error) -> ErrorKind {
+    if let Some(status) = err.status() {
+        status_kind(status.as_u16())
+    } else if err.is_timeout() {
+        ErrorKind::Timeout
+    } else if err.is_decode() || err.is_body() {
+        ErrorKind::Protocol
+    } else {
+        ErrorKind::Network
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Self::new(reqwest_kind(&err), err)
+    }
+}
+
+impl From<crate::session::LoginError> for Error {
+    fn from(err: crate::session::LoginError) -> Self {
+        use crate::session::LoginError;
+
+        let kind = match &err {
+            LoginError::Reqwest(inner) => reqwest_kind(inner),
+            LoginError::Deserializing(_) => ErrorKind::Protocol,
+        };
+        Self::new(kind, err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Self::new(ErrorKind::Protocol, err)
+    }
+}
+
+impl From<tokio::time::error::Elapsed> for Error {
+    fn from(err: tokio::time::error::Elapsed) -> Self {
+        Self::new(ErrorKind::Timeout, err)
+    }
+}
+
+#[cfg(feature = "encoding")]
+impl From<crate::encoding::EncodingError> for Error {
+    fn from(err: crate::encoding::EncodingError) -> Self {
+        Self::new(ErrorKind::Encoding, err)
+    }
+}
+
+#[cfg(feature = "cloud")]
+mod cloud {
+    use std::sync::Arc;
+
+    use tokio_tungstenite::tungstenite;
+
+    use super::{ status_kind, Error, ErrorKind };
+    use crate::{ cloud::{ NextError, SendError, SetError }, rpc::RpcError };
+
+    fn websocket_kind(err: &tungstenite::Error) -> ErrorKind {
+        use tungstenite::Error as WsError;
+
+        match err {
+            WsError::ConnectionClosed | WsError::AlreadyClosed => ErrorKind::Closed,
+            WsError::Io(_) | WsError::Tls(_) | WsError::WriteBufferFull(_) => ErrorKind::Network,
+            WsError::Http(response) => status_kind(response.status().as_u16()),
+            _ => ErrorKind::Protocol,
+        }
+    }
+
+    impl From<tungstenite::Error> for Error {
+        fn from(err: tungstenite::Error) -> Self {
+            Self::new(websocket_kind(&err), err)
+        }
+    }
+
+    impl From<Arc<tungstenite::Error>> for Error {
+        fn from(err: Arc<tungstenite::Error>) -> Self {
+            Self::new(websocket_kind(&err), err)
+        }
+    }
+
+    impl SendError {
+        pub fn kind(&self) -> ErrorKind {
+            match self {
+                Self::WebSocket(err) => websocket_kind(err),
+                Self::Serializing(_) => ErrorKind::Protocol,
+                Self::Closed => ErrorKind::Closed,
+            }
+        }
+    }
+
+    impl NextError {
+        pub fn kind(&self) -> ErrorKind {
+            match self {
+                Self::WebSocket(err) => websocket_kind(err),
+                Self::ToText | Self::Deserializing(_) => ErrorKind::Protocol,
+                Self::Lagged(_) => ErrorKind::Lagged,
+            }
+        }
+    }
+
+    impl SetError {
+        pub fn kind(&self) -> ErrorKind {
+            match self {
+                Self::NotNumeric | Self::TooLong { .. } => ErrorKind::Encoding,
+                Self::Send(err) => err.kind(),
+            }
+        }
+    }
+
+    impl RpcError {
+        pub fn kind(&self) -> ErrorKind {
+            match self {
+                Self::Encoding(_) | Self::Decoding(_) => ErrorKind::Encoding,
+                Self::Timeout => ErrorKind::Timeout,
+                Self::Set(err) => err.kind(),
+            }
+        }
+    }
+
+    macro_rules! impl_from_kinded {
+        ($($typ:ty),*) => {
+            $(
+                impl From<$typ> for Error {
+                    fn from(err: $typ) -> Self {
+                        Self::new(err.kind(), err)
+                    }
+                }
+            )*
+        };
+    }
+
+    impl_from_kinded!(SendError, NextError, SetError, RpcError);
+}
@@ -1,10 +1,20 @@
+// The derive macros refer to `::scratchback`, which unit tests of this crate need to resolve too.
+#[cfg(test)]
+extern crate self as scratchback;
+
 #[cfg(feature = "encoding")]
 pub mod encoding;
 
 #[cfg(feature = "cloud")]
 pub mod cloud;
 
+#[cfg(feature = "cloud")]
+pub mod rpc;
+
+pub mod error;
 pub mod session;
 
+pub use error::{ Error, ErrorKind };
+
 // Re-exports
 pub use moving;
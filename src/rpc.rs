@@ -0,0 +1,345 @@
+//! Request/response over cloud variables.
+//!
+//! A Scratch project writes a request to one cloud variable and the server answers in another. Both
+//! values are encoded with [`Encoding`], prefixed with a request id that the client picks and the
+//! server echoes back, so a client can tell its answer apart from answers to other clients:
+//!
+//! ```text
+//! <request id> • <ScratchObject>
+//! ```
+//!
+//! Example:
+//! ```no_run
+//! use scratchback::{ cloud::Cloud, encoding::ScratchObject, rpc::Rpc };
+//!
+//! #[derive(ScratchObject)]
+//! struct Lookup {
+//!     #[id(0)]
+//!     username: String,
+//! }
+//!
+//! #[derive(ScratchObject)]
+//! struct Score {
+//!     #[id(0)]
+//!     score: u32,
+//! }
+//!
+//! # async fn run() -> Result<(), Box<dyn core::error::Error>> {
+//! let cloud = Cloud::connect("bot".to_string()).await?;
+//! let project = cloud.project("123456".to_string());
+//! project.handshake().await?;
+//!
+//! Rpc::new(project, "request", "response")
+//!     .serve(|lookup: Lookup| async move { Score { score: lookup.username.len() as u32 } })
+//!     .await;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{ future::Future, sync::{ atomic::{ AtomicU32, Ordering }, Arc }, time::Duration };
+
+use futures_util::StreamExt;
+use tokio::sync::Semaphore;
+
+use crate::{
+    cloud::{ cloud_name, random_unit, CloudEvent, CloudMethod, CloudProject, SetError },
+    encoding::{ Encoding, EncodingError, ScratchObject },
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum RpcError {
+    #[error("Failed to encode the request: {0}")] Encoding(EncodingError),
+    #[error("Failed to decode the response: {0}")] Decoding(EncodingError),
+    #[error("No response in time")] Timeout,
+    #[error("Failed to set the request: {0}")] Set(SetError),
+}
+
+/// Serves (or calls) requests over a pair of cloud variables of one project.
+///
+/// Responses are ordinary sets, so do not [coalesce](crate::cloud::CloudBuilder::coalesce) them away
+/// when several clients may be waiting.
+pub struct Rpc {
+    project: Arc<CloudProject>,
+    request_var: String,
+    response_var: String,
+    timeout: Duration,
+    concurrency: usize,
+}
+
+impl Rpc {
+    pub fn new(project: CloudProject, request_var: &str, response_var: &str) -> Self {
+        Self {
+            project: Arc::new(project),
+            request_var: cloud_name(request_var),
+            response_var: cloud_name(response_var),
+            timeout: Duration::from_secs(10),
+            concurrency: 16,
+        }
+    }
+
+    /// How long a handler (or a call) may take before it is abandoned. Defaults to 10 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How many requests are handled at once. Defaults to 16.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    pub fn project(&self) -> &CloudProject {
+        &self.project
+    }
+
+    /// Answer requests until the connection closes.
+    ///
+    /// Every request runs in its own task. Requests that fail to decode and handlers that time out
+    /// get no response.
+    pub async fn serve<Req, Res, H, Fut>(&self, handler: H)
+        where
+            Req: ScratchObject + Send + 'static,
+            Res: ScratchObject + Send + 'static,
+            H: Fn(Req) -> Fut + Send + Sync + 'static,
+            Fut: Future<Output = Res> + Send + 'static
+    {
+        let handler = Arc::new(handler);
+        let permits = Arc::new(Semaphore::new(self.concurrency));
+
+        while let Some(item) = self.project.next().await {
+            let Ok(CloudMethod::Set { name, value, .. }) = item else {
+                continue;
+            };
+            if name != self.request_var {
+                continue;
+            }
+            let Some((id, payload)) = unframe(&value) else {
+                continue;
+            };
+            let Some(request) = Req::from_sb_encoded(payload) else {
+                continue;
+            };
+
+            let Ok(permit) = permits.clone().acquire_owned().await else {
+                return;
+            };
+            let project = self.project.clone();
+            let handler = handler.clone();
+            let response_var = self.response_var.clone();
+            let timeout = self.timeout;
+            tokio::spawn(async move {
+                let _permit = permit;
+                let Ok(response) = tokio::time::timeout(timeout, handler(request)).await else {
+                    return;
+                };
+                let Ok(value) = response.try_sb_encode().and_then(|payload| frame(&id, &payload)) else {
+                    return;
+                };
+                let _ = project.set(&response_var, &value).await;
+            });
+        }
+    }
+
+    /// Send a request and wait for its response, acting as a client.
+    pub async fn call<Req: ScratchObject, Res: ScratchObject>(&self, request: Req) -> Result<Res, RpcError> {
+        let id = next_id().to_string();
+        let value = request
+            .try_sb_encode()
+            .and_then(|payload| frame(&id, &payload))
+            .map_err(RpcError::Encoding)?;
+
+        // Every set on its own: several responses can arrive at once, and only one of them may be ours.
+        let mut responses = self.project.stream();
+        self.project.set(&self.request_var, &value).await.map_err(RpcError::Set)?;
+
+        let wait = async {
+            while let Some(event) = responses.next().await {
+                let Ok(CloudEvent::Set { name, value, .. }) = event else {
+                    continue;
+                };
+                if name != self.response_var {
+                    continue;
+                }
+                if let Some((reply_id, payload)) = unframe(&value) && reply_id == id {
+                    return Res::try_from_sb_encoded(payload).map_err(RpcError::Decoding);
+                }
+            }
+            Err(RpcError::Timeout)
+        };
+        tokio::time::timeout(self.timeout, wait).await.map_err(|_| RpcError::Timeout)?
+    }
+}
+
+/// Request ids for [`Rpc::call`], starting at a random point so separate processes rarely collide.
+fn next_id() -> u32 {
+    static NEXT: AtomicU32 = AtomicU32::new(0);
+    if NEXT.load(Ordering::Relaxed) == 0 {
+        let _ = NEXT.compare_exchange(0, (random_unit() * 1_000_000.0) as u32 + 1, Ordering::Relaxed, Ordering::Relaxed);
+    }
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
+/// `<request id> • <payload>`, where the payload is already encoded.
+fn frame(id: &str, payload: &str) -> Result<String, EncodingError> {
+    Ok(format!("{}{}{}", Encoding::try_encode(id)?, Encoding::SPLITTER_ENCODED, payload))
+}
+
+/// Split off the request id at the first splitter.
+fn unframe(value: &str) -> Option<(String, &str)> {
+    let split = Encoding::find_splitter(value)?;
+    Some((Encoding::decode(&value[..split])?, &value[split + 2..]))
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use crate::cloud::{ server::{ CloudServer, ServerState }, Cloud };
+
+    use super::*;
+
+    #[derive(ScratchObject, Debug, PartialEq)]
+    struct Lookup {
+        #[id(0)]
+        username: String,
+    }
+
+    #[derive(ScratchObject, Debug, PartialEq)]
+    struct Score {
+        #[id(0)]
+        score: u32,
+    }
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    async fn start() -> (String, ServerState) {
+        let server = CloudServer::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = server.endpoint().unwrap();
+        let state = server.state();
+        tokio::spawn(server.run());
+        (endpoint, state)
+    }
+
+    /// A project that has handshaked for sure, so it receives every later set.
+    async fn join(endpoint: &str, state: &ServerState, user: &str) -> CloudProject {
+        let cloud = Cloud::builder(user.to_string()).endpoint(endpoint).connect().await.unwrap();
+        let project = cloud.project("1".to_string());
+        let mut ready = project.watch(user);
+        project.handshake().await.unwrap();
+
+        // Arrives either broadcast or in the handshake dump.
+        state.set("1", &cloud_name(user), "1");
+        tokio::time::timeout(TIMEOUT, ready.wait_for(Option::is_some)).await.unwrap().unwrap();
+        project
+    }
+
+    /// Scores are the length of the name, and take that many tenths of a second.
+    async fn serve(endpoint: &str, state: &ServerState) {
+        let project = join(endpoint, state, "server").await;
+        tokio::spawn(async move {
+            Rpc::new(project, "request", "response")
+                .serve(|lookup: Lookup| async move {
+                    let score = lookup.username.len() as u32;
+                    tokio::time::sleep(Duration::from_millis(100) * score).await;
+                    Score { score }
+                })
+                .await;
+        });
+    }
+
+    fn lookup(username: &str) -> Lookup {
+        Lookup { username: username.to_string() }
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let (endpoint, state) = start().await;
+        serve(&endpoint, &state).await;
+        let rpc = Rpc::new(join(&endpoint, &state, "client").await, "request", "response").timeout(TIMEOUT);
+
+        assert_eq!(rpc.call::<_, Score>(lookup("ab")).await.unwrap(), Score { score: 2 });
+    }
+
+    #[tokio::test]
+    async fn replies_out_of_order() {
+        let (endpoint, state) = start().await;
+        serve(&endpoint, &state).await;
+        let rpc = Rpc::new(join(&endpoint, &state, "client").await, "request", "response").timeout(TIMEOUT);
+
+        // The first call is answered last, after the answer to the second one.
+        let (slow, fast) = tokio::join!(
+            rpc.call::<_, Score>(lookup("abcde")),
+            async {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                rpc.call::<_, Score>(lookup("a")).await
+            }
+        );
+        assert_eq!(slow.unwrap(), Score { score: 5 });
+        assert_eq!(fast.unwrap(), Score { score: 1 });
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn replies_in_one_frame() {
+        use futures_util::SinkExt;
+        use tokio_tungstenite::tungstenite::Message;
+
+        let (endpoint, state) = start().await;
+        let rpc = Rpc::new(join(&endpoint, &state, "client").await, "request", "response").timeout(TIMEOUT);
+
+        // Answers by hand, so that both responses reach the server in one frame.
+        let (mut socket, _) = tokio_tungstenite::connect_async(endpoint.as_str()).await.unwrap();
+        let handshake = serde_json::json!({ "method": "handshake", "user": "server", "project_id": "1" });
+        socket.send(Message::text(handshake.to_string())).await.unwrap();
+        // The dump of "☁ client", after which the handshake went through.
+        socket.next().await.unwrap().unwrap();
+
+        let answer = async {
+            let mut replies = Vec::new();
+            while replies.len() < 2 {
+                let message = socket.next().await.unwrap().unwrap().into_text().unwrap();
+                let Ok(CloudMethod::Set { name, value, .. }) = serde_json::from_str(&message) else {
+                    continue;
+                };
+                if name != "☁ request" {
+                    continue;
+                }
+                let (id, payload) = unframe(&value).unwrap();
+                let score = Lookup::try_from_sb_encoded(payload).unwrap().username.len() as u32;
+                let value = frame(&id, &Score { score }.sb_encode().unwrap()).unwrap();
+                let reply = serde_json::json!({ "method": "set", "name": "☁ response", "value": value });
+                replies.push(reply.to_string());
+            }
+            socket.send(Message::text(replies.join("\n"))).await.unwrap();
+            // Blocks the calls, which share this task, until both responses have arrived.
+            std::thread::sleep(Duration::from_millis(300));
+        };
+        let (first, second, ()) = tokio::join!(
+            rpc.call::<_, Score>(lookup("a")),
+            rpc.call::<_, Score>(lookup("abc")),
+            answer
+        );
+        assert_eq!(first.unwrap(), Score { score: 1 });
+        assert_eq!(second.unwrap(), Score { score: 3 });
+    }
+
+    #[tokio::test]
+    async fn ignores_other_ids_and_times_out() {
+        let (endpoint, state) = start().await;
+        let rpc = Rpc::new(join(&endpoint, &state, "client").await, "request", "response")
+            .timeout(Duration::from_millis(300));
+
+        let answer = async {
+            let request = loop {
+                if let Some(request) = state.variables("1").get("☁ request").cloned() {
+                    break request;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            };
+            let (id, _) = unframe(&request).unwrap();
+            let other = (id.parse::<u32>().unwrap() + 1).to_string();
+            let payload = Score { score: 7 }.sb_encode().unwrap();
+            state.set("1", "☁ response", &frame(&other, &payload).unwrap());
+        };
+        let (result, ()) = tokio::join!(rpc.call::<_, Score>(lookup("ab")), answer);
+        assert!(matches!(result, Err(RpcError::Timeout)), "{result:?}");
+    }
+}
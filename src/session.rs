@@ -27,4 +27,9 @@ impl Session {
             id: session_id,
         }
     }
+
+    /// The `Cookie` header value that authenticates this session.
+    pub fn cookie(&self) -> String {
+        format!("scratchsessionsid={};", self.id)
+    }
 }